@@ -1,8 +1,12 @@
 pub(crate) mod assets;
+mod editor;
+pub mod golden;
+mod input;
 mod renderer;
-mod scene;
+pub(crate) mod scene;
+mod scripting;
 
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 
 use winit::{
     dpi::PhysicalSize,
@@ -12,29 +16,59 @@ use winit::{
 
 pub(crate) use renderer::texture::*;
 
-use crate::app::scene::{component::MeshFilter, SceneObject};
+use crate::app::scene::{
+    component::{DirectionalLight, MeshFilter, Transform},
+    SceneObject,
+};
 
 use self::{
     assets::{AssetDatabase, AssetLocation},
-    renderer::{model::load_model, Renderer},
+    editor::{
+        play::{PlayAction, PlayMode},
+        FileAction, SceneFile,
+    },
+    input::Input,
+    renderer::{
+        headless::HeadlessRenderer,
+        model::{load_model, Model},
+        Renderer,
+    },
     scene::Scene,
 };
 
+/// Renders the scene file at `scene` without a window and saves the frame to `output`.
+pub async fn render_headless(
+    scene: &Path,
+    output: &Path,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    let mut renderer = HeadlessRenderer::new(width, height).await?;
+    let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+    let scene = Scene::load(scene, &asset_db)?;
+    renderer.render_to_file(&scene, output)
+}
+
 pub struct ApplicationState {
     renderer: Renderer,
     size: winit::dpi::PhysicalSize<u32>,
     window: Window,
     scene: Scene,
+    scene_file: SceneFile,
+    /// Copy of `scene` the behaviours run on while playing
+    playing: Option<Scene>,
+    play_mode: PlayMode,
+    input: Input,
     asset_db: AssetDatabase,
     // obj_model: Model,
 }
 
 impl ApplicationState {
-    pub(super) async fn new(window: Window) -> Self {
+    pub async fn new(window: Window) -> Self {
         let size = window.inner_size();
 
         let renderer = Renderer::new(&window, size).await;
-        let asset_db = AssetDatabase::new();
+        let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
 
         let obj_model = load_model(
             "cube.obj",
@@ -45,7 +79,7 @@ impl ApplicationState {
         .await
         .unwrap();
         let model_loc = AssetLocation::Resource {
-            path: "cube.json".to_owned(),
+            path: "cube.obj".to_owned(),
             in_file_ident: None,
         };
         let obj_model = asset_db.load_model(model_loc, obj_model);
@@ -100,8 +134,14 @@ impl ApplicationState {
         obj.add_child(SceneObject::new("Child 4"));
         root.add_child(obj);
 
-        // let yml = serde_yaml::to_string(&scene).unwrap();
-        // println!("{}", yml);
+        let sun = SceneObject::new("Sun");
+        sun.set_transform(Transform::from_parts(
+            cgmath::vec3(0.0, 0.0, 0.0),
+            Transform::euler_rotation(cgmath::vec3(-45.0, 30.0, 0.0)),
+            cgmath::vec3(1.0, 1.0, 1.0),
+        ));
+        sun.add_component(DirectionalLight::new());
+        root.add_child(sun);
 
         Self {
             renderer,
@@ -111,6 +151,10 @@ impl ApplicationState {
             // instances,
             // instance_buffer,
             scene,
+            scene_file: SceneFile::new(),
+            playing: None,
+            play_mode: PlayMode::new(),
+            input: Input::default(),
             asset_db,
         }
     }
@@ -119,31 +163,108 @@ impl ApplicationState {
         &self.window
     }
 
-    pub(super) fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.renderer.resize(new_size);
         }
     }
 
-    #[allow(unused_variables)]
-    pub(super) fn input(&mut self, event: &WindowEvent) -> bool {
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.input.event(event);
         false
     }
 
-    pub(super) fn event<T>(&mut self, event: &Event<T>) {
+    pub fn event<T>(&mut self, event: &Event<T>) {
         self.renderer.event(&self.window, event);
     }
 
-    pub(super) fn update(&mut self, dt: Duration) {
-        self.renderer.update(dt, &self.window, &self.scene);
+    /// The scene shown and edited, the running copy while playing.
+    fn active_scene(&self) -> &Scene {
+        self.playing.as_ref().unwrap_or(&self.scene)
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        if let Some(playing) = &self.playing {
+            playing.update(dt, &self.asset_db, &self.input);
+        }
+        let scene = self.active_scene().clone();
+        self.renderer.update(
+            dt,
+            &self.window,
+            &scene,
+            &mut self.scene_file,
+            &mut self.play_mode,
+        );
+        if let Some(action) = self.play_mode.take_action() {
+            self.apply_play_action(action);
+        }
+        if let Some(action) = self.scene_file.take_action() {
+            self.apply_file_action(action);
+        }
+        self.input.end_frame();
+    }
+
+    fn apply_play_action(&mut self, action: PlayAction) {
+        match action {
+            PlayAction::Play => match self.scene.play(&self.asset_db) {
+                Ok(scene) => self.playing = Some(scene),
+                Err(e) => self
+                    .scene_file
+                    .report_error(format!("Failed to start playing: {e:#}")),
+            },
+            PlayAction::Stop => {
+                if let Some(playing) = self.playing.take() {
+                    playing.stop(&self.asset_db, &self.input);
+                }
+            }
+        }
+        self.play_mode.set_playing(self.playing.is_some());
+    }
+
+    fn apply_file_action(&mut self, action: FileAction) {
+        // File actions work on the edited scene, stop so the change is visible
+        if !matches!(action, FileAction::Save(_)) {
+            self.apply_play_action(PlayAction::Stop);
+        }
+        match action {
+            FileAction::New => {
+                self.scene = Scene::new("Untitled Scene");
+                self.scene_file.set_path(None);
+            }
+            FileAction::Open(path) => match Scene::load(&path, &self.asset_db) {
+                Ok(scene) => {
+                    self.scene = scene;
+                    self.scene_file.set_path(Some(path));
+                }
+                Err(e) => self.scene_file.report_error(format!("{e:#}")),
+            },
+            FileAction::Save(path) => match self.scene.save(&path) {
+                Ok(()) => self.scene_file.set_path(Some(path)),
+                Err(e) => self.scene_file.report_error(format!("{e:#}")),
+            },
+            FileAction::Import(path) => {
+                match self
+                    .asset_db
+                    .resolve::<Model>(AssetLocation::resource(path.clone()))
+                {
+                    Ok(model) => {
+                        let obj = model.asset().instantiate(path);
+                        self.scene.add_child(&self.scene.root(), obj);
+                    }
+                    Err(e) => self.scene_file.report_error(format!("{e:#}")),
+                }
+            }
+        }
     }
 
-    pub(super) fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.renderer.render(&self.scene)
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // Not `active_scene`, that would borrow all of `self`
+        let scene = self.playing.as_ref().unwrap_or(&self.scene);
+        self.renderer.render(scene)
     }
 
-    pub(super) fn size(&self) -> &PhysicalSize<u32> {
+    pub fn size(&self) -> &PhysicalSize<u32> {
         &self.size
     }
 }
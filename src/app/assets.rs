@@ -2,11 +2,20 @@ use std::{
     cell::{Ref, RefCell, RefMut},
     collections::HashMap,
     fmt::Debug,
+    path::Path,
     rc::Rc,
+    sync::Arc,
 };
 
+use anyhow::{anyhow, bail};
+use serde::{de::Error, Deserialize, Serialize};
+
 use super::{
-    renderer::model::{Material, Mesh, Model},
+    renderer::{
+        gltf_loader::load_gltf,
+        model::{load_model, Material, Mesh, Model},
+    },
+    scripting::ScriptSource,
     Texture,
 };
 
@@ -16,22 +25,119 @@ pub struct AssetDatabase {
     data: Rc<RefCell<InterDatabase>>,
 }
 
+thread_local! {
+    // Database used to resolve AssetHandles while deserializing
+    static RESOLVING_DATABASE: RefCell<Option<AssetDatabase>> = RefCell::new(None);
+}
+
 asset_type!(Mesh, mesh, meshes, load_mesh);
 asset_type!(Texture, texture, textures, load_texture);
 asset_type!(Material, material, materials, load_material);
 asset_type!(Model, model, models, load_model);
+asset_type!(ScriptSource, script, scripts, load_script);
 
 impl AssetDatabase {
-    pub fn new() -> Self {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
         AssetDatabase {
             data: Rc::new(RefCell::new(InterDatabase {
+                device,
+                queue,
                 textures: HashMap::new(),
                 meshes: HashMap::new(),
                 materials: HashMap::new(),
                 models: HashMap::new(),
+                scripts: HashMap::new(),
             })),
         }
     }
+
+    /// Returns the asset at `location`, loading the resource it lives in if it is not known yet.
+    pub fn resolve<T: Asset>(&self, location: AssetLocation) -> anyhow::Result<AssetHandle<T>> {
+        if let Some(handle) = T::lookup(self, &location) {
+            return Ok(handle);
+        }
+        // Importing a file again would replace the handles objects already hold with copies
+        if !self.is_resource_loaded(&location) {
+            self.load_resource(&location)?;
+        }
+        T::lookup(self, &location)
+            .ok_or_else(|| anyhow!("Asset {location:?} not found in its resource"))
+    }
+
+    /// Whether any asset from the file `location` lives in is registered.
+    fn is_resource_loaded(&self, location: &AssetLocation) -> bool {
+        let AssetLocation::Resource { path, .. } = location else {
+            return false;
+        };
+        let from_file = |other: &AssetLocation| match other {
+            AssetLocation::Resource { path: other, .. } => other == path,
+            AssetLocation::Builtin { .. } => false,
+        };
+        let data = self.data.borrow();
+        data.textures.keys().any(from_file)
+            || data.meshes.keys().any(from_file)
+            || data.materials.keys().any(from_file)
+            || data.models.keys().any(from_file)
+            || data.scripts.keys().any(from_file)
+    }
+
+    /// Reads the scripts changed on disk again, see `ScriptSource::reload_if_changed`.
+    pub fn reload_changed_scripts(&self) {
+        let scripts = self.data.borrow().scripts.values().cloned().collect::<Vec<_>>();
+        for script in scripts {
+            if let Err(e) = script.asset_mut().reload_if_changed() {
+                log::error!("{e:#}");
+            }
+        }
+    }
+
+    /// Runs `f` with this database used to resolve every `AssetHandle` that gets deserialized.
+    pub fn resolve_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = RESOLVING_DATABASE.with(|db| db.replace(Some(self.clone())));
+        let result = f();
+        RESOLVING_DATABASE.with(|db| *db.borrow_mut() = previous);
+        result
+    }
+
+    fn load_resource(&self, location: &AssetLocation) -> anyhow::Result<()> {
+        let (device, queue) = {
+            let data = self.data.borrow();
+            (Arc::clone(&data.device), Arc::clone(&data.queue))
+        };
+        let path = match location {
+            AssetLocation::Builtin { idnetifying_name } => {
+                let texture = Texture::builtin(idnetifying_name, &device, &queue)?;
+                self.load_texture(location.clone(), texture);
+                return Ok(());
+            }
+            AssetLocation::Resource { path, .. } => path,
+        };
+
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("obj") => {
+                let model = pollster::block_on(load_model(path, &device, &queue, self.clone()))?;
+                self.load_model(AssetLocation::resource(path), model);
+            }
+            Some("gltf" | "glb") => {
+                let model = pollster::block_on(load_gltf(path, &device, &queue, self.clone()))?;
+                self.load_model(AssetLocation::resource(path), model);
+            }
+            Some("png" | "jpg" | "jpeg") => {
+                let texture = pollster::block_on(Texture::load_texture(path, &device, &queue))?;
+                self.load_texture(AssetLocation::resource(path), texture);
+            }
+            Some("rhai") => {
+                let script = ScriptSource::load(path)?;
+                self.load_script(AssetLocation::resource(path), script);
+            }
+            _ => bail!("No loader for resource {location:?}"),
+        }
+        Ok(())
+    }
 }
 
 impl Clone for AssetDatabase {
@@ -43,16 +149,20 @@ impl Clone for AssetDatabase {
 }
 
 struct InterDatabase {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+
     textures: HashMap<AssetLocation, AssetHandle<Texture>>,
     meshes: HashMap<AssetLocation, AssetHandle<Mesh>>,
     materials: HashMap<AssetLocation, AssetHandle<Material>>,
     models: HashMap<AssetLocation, AssetHandle<Model>>,
+    scripts: HashMap<AssetLocation, AssetHandle<ScriptSource>>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum AssetLocation {
     Builtin {
-        idnetifying_name: &'static str,
+        idnetifying_name: String,
     },
     Resource {
         path: String,
@@ -66,7 +176,53 @@ impl Debug for AssetLocation {
     }
 }
 
+impl Serialize for AssetLocation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_ident())
+    }
+}
+
+impl<'de> Deserialize<'de> for AssetLocation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let ident = String::deserialize(deserializer)?;
+        AssetLocation::from_ident(&ident)
+            .ok_or_else(|| D::Error::custom(format!("Invalid asset location: {ident}")))
+    }
+}
+
 impl AssetLocation {
+    pub fn resource<S: Into<String>>(path: S) -> AssetLocation {
+        AssetLocation::Resource {
+            path: path.into(),
+            in_file_ident: None,
+        }
+    }
+
+    pub fn from_ident(ident: &str) -> Option<AssetLocation> {
+        if let Some(name) = ident.strip_prefix("builtin:") {
+            Some(AssetLocation::Builtin {
+                idnetifying_name: name.to_string(),
+            })
+        } else if let Some(resource) = ident.strip_prefix("res:") {
+            let (path, in_file_ident) = match resource.split_once('#') {
+                Some((path, in_file_ident)) => (path, Some(in_file_ident.to_string())),
+                None => (resource, None),
+            };
+            Some(AssetLocation::Resource {
+                path: path.to_string(),
+                in_file_ident,
+            })
+        } else {
+            None
+        }
+    }
+
     pub fn to_ident(&self) -> String {
         match self {
             AssetLocation::Builtin { idnetifying_name } => format!("builtin:{idnetifying_name}"),
@@ -114,6 +270,33 @@ impl<T> Clone for AssetHandle<T> {
     }
 }
 
+impl<T> Serialize for AssetHandle<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.location.serialize(serializer)
+    }
+}
+
+impl<'de, T: Asset> Deserialize<'de> for AssetHandle<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let location = AssetLocation::deserialize(deserializer)?;
+        let db = RESOLVING_DATABASE
+            .with(|db| db.borrow().clone())
+            .ok_or_else(|| D::Error::custom("No AssetDatabase in scope to resolve assets"))?;
+        db.resolve(location).map_err(|e| D::Error::custom(format!("{e:#}")))
+    }
+}
+
+/// Implemented by every type stored in the `AssetDatabase`.
+pub trait Asset: Sized {
+    fn lookup(db: &AssetDatabase, location: &AssetLocation) -> Option<AssetHandle<Self>>;
+}
+
 impl<T> AssetHandle<T> {
     pub fn asset(&self) -> Ref<'_, T> {
         self.asset.borrow()
@@ -146,6 +329,40 @@ mod asset_helpers {
                     handle
                 }
             }
+
+            impl Asset for $type {
+                fn lookup(db: &AssetDatabase, location: &AssetLocation) -> Option<AssetHandle<Self>> {
+                    db.$name(location.clone())
+                }
+            }
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::renderer::headless::HeadlessRenderer;
+
+    /// Needs a GPU or a software adapter, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn missing_ident_does_not_import_the_file_again() {
+        let renderer = pollster::block_on(HeadlessRenderer::with_fallback_adapter(1, 1)).unwrap();
+        let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+        let location = |ident| AssetLocation::from_ident(ident).unwrap();
+        let mesh: AssetHandle<Mesh> = asset_db
+            .resolve(location("res:cube.obj#Cube_Finished_Cube.001"))
+            .unwrap();
+        let meshes = asset_db.data.borrow().meshes.len();
+
+        let error = asset_db
+            .resolve::<Mesh>(location("res:cube.obj#Typo"))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("not found"), "{error}");
+        assert_eq!(asset_db.data.borrow().meshes.len(), meshes);
+        let again = asset_db.mesh(location("res:cube.obj#Cube_Finished_Cube.001"));
+        assert!(again.is_some_and(|again| Rc::ptr_eq(&again.asset, &mesh.asset)));
+    }
+}
@@ -0,0 +1,144 @@
+pub mod gizmo;
+pub mod play;
+
+use std::path::PathBuf;
+
+use imgui::Ui;
+
+use crate::gui::ui;
+
+const OPEN_POPUP: &str = "Open Scene";
+const SAVE_AS_POPUP: &str = "Save Scene As";
+const IMPORT_POPUP: &str = "Import Model";
+const ERROR_POPUP: &str = "Error";
+
+pub enum FileAction {
+    New,
+    Open(PathBuf),
+    Save(PathBuf),
+    Import(String),
+}
+
+/// State of the "File" menu: the path of the open scene and the requested action.
+pub struct SceneFile {
+    path: Option<PathBuf>,
+    path_input: String,
+    error: Option<String>,
+    action: Option<FileAction>,
+}
+
+impl SceneFile {
+    pub fn new() -> SceneFile {
+        SceneFile {
+            path: None,
+            path_input: String::new(),
+            error: None,
+            action: None,
+        }
+    }
+
+    pub fn set_path(&mut self, path: Option<PathBuf>) {
+        self.path = path;
+    }
+
+    pub fn take_action(&mut self) -> Option<FileAction> {
+        self.action.take()
+    }
+
+    pub fn report_error<S: Into<String>>(&mut self, error: S) {
+        let error = error.into();
+        log::error!("{error}");
+        self.error = Some(error);
+    }
+}
+
+impl SceneFile {
+    pub fn gui(&mut self, ui: &Ui) {
+        let mut open_popup = None;
+        if let Some(_menu_bar) = ui.begin_main_menu_bar() {
+            if let Some(_file_menu) = ui.begin_menu("File") {
+                if ui.menu_item("New") {
+                    self.action = Some(FileAction::New);
+                }
+                if ui.menu_item("Open...") {
+                    open_popup = Some(OPEN_POPUP);
+                }
+                if ui.menu_item("Save") {
+                    match &self.path {
+                        Some(path) => self.action = Some(FileAction::Save(path.clone())),
+                        None => open_popup = Some(SAVE_AS_POPUP),
+                    }
+                }
+                if ui.menu_item("Save As...") {
+                    open_popup = Some(SAVE_AS_POPUP);
+                }
+                ui.separator();
+                if ui.menu_item("Import Model...") {
+                    open_popup = Some(IMPORT_POPUP);
+                }
+            }
+        }
+
+        // Popups have to be opened outside of the menu to share its id stack
+        if let Some(popup) = open_popup {
+            self.path_input = match (popup, &self.path) {
+                (IMPORT_POPUP, _) => String::new(),
+                (_, Some(path)) => path.display().to_string(),
+                (_, None) => "scene.yaml".to_string(),
+            };
+            ui.open_popup(popup);
+        }
+        if self.error.is_some() {
+            ui.open_popup(ERROR_POPUP);
+        }
+
+        if let Some(path) = self.path_popup(ui, OPEN_POPUP, "Open") {
+            self.action = Some(FileAction::Open(path));
+        }
+        if let Some(path) = self.path_popup(ui, SAVE_AS_POPUP, "Save") {
+            self.action = Some(FileAction::Save(path));
+        }
+        if let Some(path) = self.path_popup(ui, IMPORT_POPUP, "Import") {
+            // Models are resources, so their path is relative to the res folder
+            self.action = Some(FileAction::Import(path.display().to_string()));
+        }
+
+        if let Some(_popup) = ui
+            .modal_popup_config(ERROR_POPUP)
+            .always_auto_resize(true)
+            .begin_popup()
+        {
+            ui.text(self.error.as_deref().unwrap_or_default());
+            if ui.button("Ok") {
+                self.error = None;
+                ui.close_current_popup();
+            }
+        }
+    }
+
+    fn path_popup(&mut self, ui: &Ui, popup: &str, confirm: &str) -> Option<PathBuf> {
+        let mut path = None;
+        if let Some(_popup) = ui
+            .modal_popup_config(popup)
+            .always_auto_resize(true)
+            .begin_popup()
+        {
+            ui::input_text(ui, "Path:", &mut self.path_input, Some("scene.yaml"));
+            if ui.button(confirm) && !self.path_input.is_empty() {
+                path = Some(PathBuf::from(&self.path_input));
+                ui.close_current_popup();
+            }
+            ui.same_line();
+            if ui.button("Cancel") {
+                ui.close_current_popup();
+            }
+        }
+        path
+    }
+}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
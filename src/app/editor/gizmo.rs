@@ -0,0 +1,511 @@
+use cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Quaternion, Rotation3, SquareMatrix,
+    Transform as _, Vector3, Vector4,
+};
+use imgui::{Drag, Key, MouseButton, Ui};
+
+use crate::app::scene::{component::Transform, SceneObject};
+
+/// On screen length of the handles.
+const HANDLE_PIXELS: f32 = 90.0;
+/// How close the mouse has to be to a handle to grab it.
+const PICK_PIXELS: f32 = 7.0;
+const RING_SEGMENTS: usize = 48;
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.2, 0.2, 1.0],
+    [0.3, 0.85, 0.3, 1.0],
+    [0.25, 0.45, 1.0, 1.0],
+];
+const CENTER_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const HOVER_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// Axes the gizmo moves and rotates along, scaling always uses the local axes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GizmoSpace {
+    Local,
+    World,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Handle {
+    Axis(usize),
+    /// Uniform scale
+    Center,
+}
+
+/// Screen area the scene is drawn to, with the view projection it was drawn with.
+pub struct ViewportScreen {
+    pub min: [f32; 2],
+    pub size: [f32; 2],
+    pub view_proj: Matrix4<f32>,
+    pub inverse_view_proj: Matrix4<f32>,
+}
+
+#[derive(Clone)]
+struct GizmoDrag {
+    handle: Handle,
+    start: Transform,
+    parent: Matrix4<f32>,
+    origin: Point3<f32>,
+    /// World space direction of the dragged axis
+    axis: Vector3<f32>,
+    start_mouse: [f32; 2],
+    /// Position along the axis for translation and screen angle for rotation
+    start_param: f32,
+}
+
+/// Translate, rotate and scale handles for the selected object, drawn over the viewport.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub space: GizmoSpace,
+    pub snapping: bool,
+    /// Snap increments of translation, rotation in degrees and scale
+    pub increments: [f32; 3],
+    drag: Option<GizmoDrag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Gizmo {
+        Gizmo {
+            mode: GizmoMode::Translate,
+            space: GizmoSpace::World,
+            snapping: false,
+            increments: [0.5, 15.0, 0.1],
+            drag: None,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Mode, space and snapping controls, call inside the menu bar of the viewport window.
+    pub fn menu_bar(&mut self, ui: &Ui) {
+        ui.radio_button("Move (W)", &mut self.mode, GizmoMode::Translate);
+        ui.radio_button("Rotate (E)", &mut self.mode, GizmoMode::Rotate);
+        ui.radio_button("Scale (R)", &mut self.mode, GizmoMode::Scale);
+        ui.separator();
+        let space = match self.space {
+            GizmoSpace::Local => "Local",
+            GizmoSpace::World => "World",
+        };
+        if ui.small_button(space) {
+            self.space = match self.space {
+                GizmoSpace::Local => GizmoSpace::World,
+                GizmoSpace::World => GizmoSpace::Local,
+            };
+        }
+        ui.checkbox("Snap (Ctrl)", &mut self.snapping);
+        ui.menu("Increments", || {
+            Drag::new("Move")
+                .speed(0.01)
+                .range(0.001, f32::MAX)
+                .build(ui, &mut self.increments[0]);
+            Drag::new("Rotate")
+                .speed(0.5)
+                .range(0.1, 180.0)
+                .build(ui, &mut self.increments[1]);
+            Drag::new("Scale")
+                .speed(0.01)
+                .range(0.001, f32::MAX)
+                .build(ui, &mut self.increments[2]);
+        });
+    }
+
+    /// Draws the handles of `object` and applies drags to its transform, call inside the
+    /// viewport window. Returns true while the mouse is over a handle or dragging one.
+    pub fn update(&mut self, ui: &Ui, object: &SceneObject, screen: &ViewportScreen) -> bool {
+        self.shortcuts(ui);
+
+        let parent = object
+            .parent()
+            .map_or(Matrix4::identity(), |p| p.world_matrix());
+        let world = object.world_matrix();
+        let origin = Point3::from_vec(object.world_position());
+        let Some(center) = screen.project(origin) else {
+            self.drag = None;
+            return false;
+        };
+        let length = HANDLE_PIXELS * screen.world_per_pixel(origin);
+        let axes = self.axes(&world);
+        let mouse = ui.io().mouse_pos;
+
+        if let Some(drag) = self.drag.clone() {
+            if ui.is_mouse_down(MouseButton::Left) {
+                if let Some(transform) = self.dragged(&drag, ui, screen, center, length) {
+                    object.set_transform(transform);
+                }
+            } else {
+                self.drag = None;
+            }
+        }
+
+        let hovered = match &self.drag {
+            Some(drag) => Some(drag.handle),
+            None if ui.is_window_hovered() => {
+                self.hovered(screen, mouse, origin, center, &axes, length)
+            }
+            None => None,
+        };
+        if let (None, Some(handle)) = (&self.drag, hovered) {
+            if ui.is_mouse_clicked(MouseButton::Left) && !ui.io().key_alt {
+                let axis = match handle {
+                    Handle::Axis(i) => axes[i],
+                    Handle::Center => Vector3::unit_x(),
+                };
+                let start_param = match self.mode {
+                    GizmoMode::Translate => axis_param(screen, mouse, origin, axis).unwrap_or(0.0),
+                    GizmoMode::Rotate => screen_angle(center, mouse),
+                    GizmoMode::Scale => 0.0,
+                };
+                self.drag = Some(GizmoDrag {
+                    handle,
+                    start: object.get_transform(),
+                    parent,
+                    origin,
+                    axis,
+                    start_mouse: mouse,
+                    start_param,
+                });
+            }
+        }
+
+        self.draw(ui, screen, origin, center, &axes, length, hovered);
+        hovered.is_some()
+    }
+
+    fn shortcuts(&mut self, ui: &Ui) {
+        // The fly camera uses these keys while the right button is held
+        if !ui.is_window_focused()
+            || ui.is_mouse_down(MouseButton::Right)
+            || ui.io().want_text_input
+        {
+            return;
+        }
+        for (key, mode) in [
+            (Key::W, GizmoMode::Translate),
+            (Key::E, GizmoMode::Rotate),
+            (Key::R, GizmoMode::Scale),
+        ] {
+            if ui.is_key_pressed(key) {
+                self.mode = mode;
+            }
+        }
+    }
+
+    /// World space directions of the handles.
+    fn axes(&self, world: &Matrix4<f32>) -> [Vector3<f32>; 3] {
+        let world_axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        if self.space == GizmoSpace::World && self.mode != GizmoMode::Scale {
+            return world_axes;
+        }
+        let columns = [world.x, world.y, world.z];
+        [0, 1, 2].map(|i| {
+            let axis = columns[i].truncate();
+            if axis.magnitude2() > f32::EPSILON {
+                axis.normalize()
+            } else {
+                world_axes[i]
+            }
+        })
+    }
+
+    fn hovered(
+        &self,
+        screen: &ViewportScreen,
+        mouse: [f32; 2],
+        origin: Point3<f32>,
+        center: [f32; 2],
+        axes: &[Vector3<f32>; 3],
+        length: f32,
+    ) -> Option<Handle> {
+        if self.mode == GizmoMode::Scale
+            && (mouse[0] - center[0]).abs() <= PICK_PIXELS
+            && (mouse[1] - center[1]).abs() <= PICK_PIXELS
+        {
+            return Some(Handle::Center);
+        }
+        let mut closest = None;
+        let mut closest_distance = PICK_PIXELS;
+        for i in 0..3 {
+            let points = match self.mode {
+                GizmoMode::Rotate => ring(screen, origin, axes, i, length),
+                GizmoMode::Translate | GizmoMode::Scale => screen
+                    .project(origin + axes[i] * length)
+                    .map_or(vec![], |tip| vec![center, tip]),
+            };
+            for segment in points.windows(2) {
+                let distance = segment_distance(mouse, segment[0], segment[1]);
+                if distance < closest_distance {
+                    closest_distance = distance;
+                    closest = Some(Handle::Axis(i));
+                }
+            }
+        }
+        closest
+    }
+
+    /// Transform of the dragged object for the current mouse position.
+    fn dragged(
+        &self,
+        drag: &GizmoDrag,
+        ui: &Ui,
+        screen: &ViewportScreen,
+        center: [f32; 2],
+        length: f32,
+    ) -> Option<Transform> {
+        let mouse = ui.io().mouse_pos;
+        // Holding ctrl inverts the snapping setting
+        let snapping = self.snapping != ui.io().key_ctrl;
+        let snap = |value: f32, increment: f32| {
+            if snapping && increment > 0.0 {
+                (value / increment).round() * increment
+            } else {
+                value
+            }
+        };
+        let start = &drag.start;
+        let parent_inverse = drag.parent.invert().unwrap_or(Matrix4::identity());
+
+        match self.mode {
+            GizmoMode::Translate => {
+                let param = axis_param(screen, mouse, drag.origin, drag.axis)?;
+                let delta = snap(param - drag.start_param, self.increments[0]);
+                let local = parent_inverse.transform_vector(drag.axis * delta);
+                Some(Transform::from_parts(
+                    start.position() + local,
+                    start.rotation(),
+                    start.scale(),
+                ))
+            }
+            GizmoMode::Rotate => {
+                let Handle::Axis(i) = drag.handle else {
+                    return None;
+                };
+                let mut delta = (screen_angle(center, mouse) - drag.start_param).to_degrees();
+                // Screen angles grow clockwise, rotations are counter clockwise around an
+                // axis pointing at the viewer
+                let (_, view_dir) = screen.ray(center);
+                if drag.axis.dot(view_dir) < 0.0 {
+                    delta = -delta;
+                }
+                let delta = Deg(snap(delta, self.increments[1]));
+                let mut transform = start.clone();
+                match self.space {
+                    GizmoSpace::Local => {
+                        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+                        transform.set_rotation(
+                            start.rotation() * Quaternion::from_axis_angle(axes[i], delta),
+                        );
+                    }
+                    GizmoSpace::World => {
+                        let axis = parent_inverse.transform_vector(drag.axis);
+                        if axis.magnitude2() <= f32::EPSILON {
+                            return None;
+                        }
+                        transform.rotate(axis, delta);
+                    }
+                }
+                Some(transform)
+            }
+            GizmoMode::Scale => {
+                let moved = [
+                    mouse[0] - drag.start_mouse[0],
+                    mouse[1] - drag.start_mouse[1],
+                ];
+                let mut scale = start.scale();
+                match drag.handle {
+                    Handle::Center => {
+                        let factor = 1.0 + moved[0] / HANDLE_PIXELS;
+                        scale = scale.map(|s| snap(s * factor, self.increments[2]));
+                    }
+                    Handle::Axis(i) => {
+                        let tip = screen.project(drag.origin + drag.axis * length)?;
+                        let direction = [tip[0] - center[0], tip[1] - center[1]];
+                        let pixels = (direction[0].powi(2) + direction[1].powi(2)).sqrt();
+                        if pixels < 1.0 {
+                            return None;
+                        }
+                        let along = (moved[0] * direction[0] + moved[1] * direction[1]) / pixels;
+                        scale[i] = snap(scale[i] * (1.0 + along / pixels), self.increments[2]);
+                    }
+                }
+                Some(Transform::from_parts(
+                    start.position(),
+                    start.rotation(),
+                    scale,
+                ))
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw(
+        &self,
+        ui: &Ui,
+        screen: &ViewportScreen,
+        origin: Point3<f32>,
+        center: [f32; 2],
+        axes: &[Vector3<f32>; 3],
+        length: f32,
+        hovered: Option<Handle>,
+    ) {
+        let draw_list = ui.get_window_draw_list();
+        let color = |handle: Handle| {
+            if hovered == Some(handle) {
+                HOVER_COLOR
+            } else {
+                match handle {
+                    Handle::Axis(i) => AXIS_COLORS[i],
+                    Handle::Center => CENTER_COLOR,
+                }
+            }
+        };
+
+        for i in 0..3 {
+            let color = color(Handle::Axis(i));
+            if self.mode == GizmoMode::Rotate {
+                let points = ring(screen, origin, axes, i, length);
+                draw_list.add_polyline(points, color).thickness(2.0).build();
+                continue;
+            }
+            let Some(tip) = screen.project(origin + axes[i] * length) else {
+                continue;
+            };
+            draw_list
+                .add_line(center, tip, color)
+                .thickness(3.0)
+                .build();
+            if self.mode == GizmoMode::Translate {
+                draw_list.add_circle(tip, 5.0, color).filled(true).build();
+            } else {
+                draw_list
+                    .add_rect(
+                        [tip[0] - 4.0, tip[1] - 4.0],
+                        [tip[0] + 4.0, tip[1] + 4.0],
+                        color,
+                    )
+                    .filled(true)
+                    .build();
+            }
+        }
+        if self.mode == GizmoMode::Scale {
+            let color = color(Handle::Center);
+            draw_list
+                .add_rect(
+                    [center[0] - 5.0, center[1] - 5.0],
+                    [center[0] + 5.0, center[1] + 5.0],
+                    color,
+                )
+                .filled(true)
+                .build();
+        }
+    }
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ViewportScreen {
+    /// Screen position of `point`, `None` if it is behind the camera.
+    fn project(&self, point: Point3<f32>) -> Option<[f32; 2]> {
+        let clip = self.view_proj * point.to_homogeneous();
+        if clip.w <= 1e-5 {
+            return None;
+        }
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        Some([
+            self.min[0] + (x * 0.5 + 0.5) * self.size[0],
+            self.min[1] + (0.5 - y * 0.5) * self.size[1],
+        ])
+    }
+
+    /// World position under the screen position `point` at normalized `depth`.
+    fn unproject(&self, point: [f32; 2], depth: f32) -> Point3<f32> {
+        let x = (point[0] - self.min[0]) / self.size[0] * 2.0 - 1.0;
+        let y = 1.0 - (point[1] - self.min[1]) / self.size[1] * 2.0;
+        Point3::from_homogeneous(self.inverse_view_proj * Vector4::new(x, y, depth, 1.0))
+    }
+
+    /// Ray from the near plane through the screen position `point`.
+    fn ray(&self, point: [f32; 2]) -> (Point3<f32>, Vector3<f32>) {
+        let near = self.unproject(point, 0.0);
+        let far = self.unproject(point, 1.0);
+        (near, (far - near).normalize())
+    }
+
+    /// Size of a screen pixel in world units at the depth of `point`.
+    fn world_per_pixel(&self, point: Point3<f32>) -> f32 {
+        let clip = self.view_proj * point.to_homogeneous();
+        let depth = clip.z / clip.w;
+        let Some(center) = self.project(point) else {
+            return 0.0;
+        };
+        let a = self.unproject(center, depth);
+        let b = self.unproject([center[0] + 1.0, center[1]], depth);
+        (b - a).magnitude()
+    }
+}
+
+/// Position along the axis through `origin` closest to the mouse ray.
+fn axis_param(
+    screen: &ViewportScreen,
+    mouse: [f32; 2],
+    origin: Point3<f32>,
+    axis: Vector3<f32>,
+) -> Option<f32> {
+    let (ray_origin, ray_dir) = screen.ray(mouse);
+    let offset = origin - ray_origin;
+    let b = axis.dot(ray_dir);
+    let denominator = 1.0 - b * b;
+    // The axis points into the screen
+    if denominator < 1e-4 {
+        return None;
+    }
+    Some((b * ray_dir.dot(offset) - axis.dot(offset)) / denominator)
+}
+
+fn screen_angle(center: [f32; 2], mouse: [f32; 2]) -> f32 {
+    (mouse[1] - center[1]).atan2(mouse[0] - center[0])
+}
+
+/// Screen points of the rotation ring around `axes[axis]`, up to where it goes behind the camera.
+fn ring(
+    screen: &ViewportScreen,
+    origin: Point3<f32>,
+    axes: &[Vector3<f32>; 3],
+    axis: usize,
+    radius: f32,
+) -> Vec<[f32; 2]> {
+    let u = axes[(axis + 1) % 3];
+    let v = axes[(axis + 2) % 3];
+    (0..=RING_SEGMENTS)
+        .map_while(|i| {
+            let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+            screen.project(origin + (u * angle.cos() + v * angle.sin()) * radius)
+        })
+        .collect()
+}
+
+fn segment_distance(point: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let ap = [point[0] - a[0], point[1] - a[1]];
+    let length = ab[0] * ab[0] + ab[1] * ab[1];
+    let t = if length > 0.0 {
+        ((ap[0] * ab[0] + ap[1] * ab[1]) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = [a[0] + ab[0] * t - point[0], a[1] + ab[1] * t - point[1]];
+    (closest[0] * closest[0] + closest[1] * closest[1]).sqrt()
+}
@@ -0,0 +1,42 @@
+use imgui::Ui;
+
+pub enum PlayAction {
+    Play,
+    Stop,
+}
+
+/// Play and Stop in the main menu bar. Playing runs a copy of the scene, everything the
+/// behaviours change is dropped with it when playing stops.
+pub struct PlayMode {
+    playing: bool,
+    action: Option<PlayAction>,
+}
+
+impl PlayMode {
+    pub fn new() -> PlayMode {
+        PlayMode {
+            playing: false,
+            action: None,
+        }
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    pub fn take_action(&mut self) -> Option<PlayAction> {
+        self.action.take()
+    }
+
+    pub fn gui(&mut self, ui: &Ui) {
+        if let Some(_menu_bar) = ui.begin_main_menu_bar() {
+            if self.playing {
+                if ui.menu_item("Stop") {
+                    self.action = Some(PlayAction::Stop);
+                }
+            } else if ui.menu_item("Play") {
+                self.action = Some(PlayAction::Play);
+            }
+        }
+    }
+}
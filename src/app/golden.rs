@@ -0,0 +1,200 @@
+//! Golden-image regression tests: every `<name>.yaml` scene in a directory is rendered by the
+//! headless renderer and compared against the reference `<name>.png` next to it.
+//! Failing cases leave `<name>.actual.png` and `<name>.diff.png` behind.
+
+use std::path::Path;
+
+use anyhow::Context;
+use image::{Rgba, RgbaImage};
+
+use super::{assets::AssetDatabase, renderer::headless::HeadlessRenderer, scene::Scene};
+
+pub const WIDTH: u32 = 256;
+pub const HEIGHT: u32 = 256;
+/// Largest per channel difference for a pixel to still count as matching.
+pub const TOLERANCE: u8 = 8;
+
+enum Outcome {
+    Passed,
+    Blessed,
+    Failed(String),
+}
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub diff: RgbaImage,
+}
+
+/// Compares two images, mismatching pixels are red in the diff image. Where the sizes differ
+/// the pixels only one of the images has all mismatch.
+pub fn compare(rendered: &RgbaImage, reference: &RgbaImage, tolerance: u8) -> Comparison {
+    let width = rendered.width().max(reference.width());
+    let height = rendered.height().max(reference.height());
+    let mut diff = RgbaImage::new(width, height);
+    let mut mismatched = 0;
+    for (x, y, out) in diff.enumerate_pixels_mut() {
+        let (Some(pixel), Some(expected)) = (
+            rendered.get_pixel_checked(x, y),
+            reference.get_pixel_checked(x, y),
+        ) else {
+            mismatched += 1;
+            *out = Rgba([255, 0, 0, 255]);
+            continue;
+        };
+        let distance = pixel
+            .0
+            .iter()
+            .zip(expected.0.iter())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0);
+        if distance > tolerance {
+            mismatched += 1;
+            *out = Rgba([255, 0, 0, 255]);
+        } else {
+            // Dimmed reference to make the mismatches easy to locate
+            let [r, g, b, _] = expected.0;
+            *out = Rgba([r / 4, g / 4, b / 4, 255]);
+        }
+    }
+    Comparison { mismatched, diff }
+}
+
+/// Runs every golden case in `dir`, `bless` overwrites the references instead of comparing.
+/// Returns whether all cases passed.
+pub async fn run(dir: &Path, bless: bool) -> anyhow::Result<bool> {
+    let mut renderer = HeadlessRenderer::with_fallback_adapter(WIDTH, HEIGHT).await?;
+    let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+
+    let mut scenes = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read golden directory {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    scenes.retain(|p| p.extension().map_or(false, |e| e == "yaml"));
+    scenes.sort();
+
+    let mut failed = 0;
+    for scene in &scenes {
+        let name = scene.display();
+        match check(&mut renderer, &asset_db, scene, bless) {
+            Ok(Outcome::Passed) => println!("{name} ... ok"),
+            Ok(Outcome::Blessed) => println!("{name} ... blessed"),
+            Ok(Outcome::Failed(reason)) => {
+                failed += 1;
+                println!("{name} ... FAILED: {reason}");
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{name} ... FAILED: {e:#}");
+            }
+        }
+    }
+    println!("{} passed, {failed} failed", scenes.len() - failed);
+
+    Ok(failed == 0)
+}
+
+fn check(
+    renderer: &mut HeadlessRenderer,
+    asset_db: &AssetDatabase,
+    scene_path: &Path,
+    bless: bool,
+) -> anyhow::Result<Outcome> {
+    let reference_path = scene_path.with_extension("png");
+    let actual_path = scene_path.with_extension("actual.png");
+    let diff_path = scene_path.with_extension("diff.png");
+
+    let scene = Scene::load(scene_path, asset_db)?;
+    let rendered = renderer.render(&scene)?;
+
+    if bless {
+        rendered.save(&reference_path)?;
+        return Ok(Outcome::Blessed);
+    }
+    if !reference_path.exists() {
+        return Ok(Outcome::Failed(format!(
+            "Missing reference {}, run with --bless to create it",
+            reference_path.display()
+        )));
+    }
+
+    let reference = image::open(&reference_path)?.to_rgba8();
+    if reference.dimensions() != rendered.dimensions() {
+        return Ok(Outcome::Failed(format!(
+            "Reference is {:?} but the frame is {:?}",
+            reference.dimensions(),
+            rendered.dimensions()
+        )));
+    }
+
+    let comparison = compare(&rendered, &reference, TOLERANCE);
+    if comparison.mismatched == 0 {
+        return Ok(Outcome::Passed);
+    }
+    rendered.save(&actual_path)?;
+    comparison.diff.save(&diff_path)?;
+    Ok(Outcome::Failed(format!(
+        "{} pixels differ, see {}",
+        comparison.mismatched,
+        diff_path.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled(4, 4, [10, 20, 30, 255]);
+        let comparison = compare(&image, &image, 0);
+        assert_eq!(comparison.mismatched, 0);
+    }
+
+    #[test]
+    fn pixel_within_tolerance_matches() {
+        let reference = filled(4, 4, [10, 20, 30, 255]);
+        let mut rendered = reference.clone();
+        rendered.put_pixel(1, 2, Rgba([10 + TOLERANCE, 20, 30, 255]));
+        assert_eq!(compare(&rendered, &reference, TOLERANCE).mismatched, 0);
+    }
+
+    #[test]
+    fn pixel_over_tolerance_mismatches() {
+        let reference = filled(4, 4, [10, 20, 30, 255]);
+        let mut rendered = reference.clone();
+        rendered.put_pixel(1, 2, Rgba([10, 20, 31 + TOLERANCE, 255]));
+        let comparison = compare(&rendered, &reference, TOLERANCE);
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.diff.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn size_mismatch_fails() {
+        let reference = filled(4, 4, [0, 0, 0, 255]);
+        let rendered = filled(4, 2, [0, 0, 0, 255]);
+        let comparison = compare(&rendered, &reference, TOLERANCE);
+        assert_eq!(comparison.mismatched, 8);
+        assert_eq!(comparison.diff.dimensions(), (4, 4));
+    }
+
+    /// Needs a GPU or a software adapter, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn cube_matches_reference() {
+        let scene = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/cube.yaml");
+        let outcome = pollster::block_on(async {
+            let mut renderer = HeadlessRenderer::with_fallback_adapter(WIDTH, HEIGHT).await?;
+            let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+            check(&mut renderer, &asset_db, &scene, false)
+        })
+        .unwrap();
+        if let Outcome::Failed(reason) = outcome {
+            panic!("{reason}");
+        }
+    }
+}
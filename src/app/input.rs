@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+/// Keyboard and mouse state collected from window events, for gameplay code to query.
+#[derive(Clone, Default)]
+pub struct Input {
+    keys_down: HashSet<VirtualKeyCode>,
+    /// Went down since the last frame
+    keys_pressed: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    mouse_position: [f32; 2],
+}
+
+impl Input {
+    pub fn event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    // Held keys repeat their press event
+                    if self.keys_down.insert(*key) {
+                        self.keys_pressed.insert(*key);
+                    }
+                }
+                ElementState::Released => {
+                    self.keys_down.remove(key);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse_position = [position.x as f32, position.y as f32];
+            }
+            // Keys released while unfocused never send their release
+            WindowEvent::Focused(false) => {
+                self.keys_down.clear();
+                self.buttons_down.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets which keys were pressed this frame, call once every frame was updated.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    /// Whether `key` went down this frame.
+    #[allow(dead_code)]
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = VirtualKeyCode> + '_ {
+        self.keys_down.iter().copied()
+    }
+
+    pub fn keys_pressed(&self) -> impl Iterator<Item = VirtualKeyCode> + '_ {
+        self.keys_pressed.iter().copied()
+    }
+
+    pub fn mouse_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    /// Cursor position in physical pixels from the top left of the window.
+    pub fn mouse_position(&self) -> [f32; 2] {
+        self.mouse_position
+    }
+}
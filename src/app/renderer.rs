@@ -1,12 +1,18 @@
-mod camera;
+pub(crate) mod camera;
+mod culling;
+mod editor_camera;
 mod framebuffer;
+pub(crate) mod gltf_loader;
+pub(crate) mod headless;
 pub mod mesh;
 pub(crate) mod model;
 pub mod pipeline;
+pub(crate) mod shadow;
 pub(crate) mod texture;
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
+use cgmath::{EuclideanSpace, Point3};
 use imgui::TextureId;
 use wgpu::{util::DeviceExt, ColorTargetState, Device, Queue};
 use winit::{dpi::PhysicalSize, event::Event, window::Window};
@@ -14,22 +20,41 @@ use winit::{dpi::PhysicalSize, event::Event, window::Window};
 #[cfg(feature = "imgui")]
 use crate::gui::{init_gui, Gui, GuiPlatform};
 
-use self::{camera::Camera, framebuffer::Framebuffer, pipeline::Pipeline};
-
-use super::scene::{component::Transform, Scene};
+use self::{
+    camera::Camera, editor_camera::EditorCamera, framebuffer::Framebuffer, pipeline::Pipeline,
+};
+
+use super::{
+    editor::{
+        gizmo::{Gizmo, ViewportScreen},
+        play::PlayMode,
+        SceneFile,
+    },
+    scene::{
+        component::{Camera as SceneCamera, Transform},
+        Scene,
+    },
+};
 
 const VSYNC: bool = true;
+const MAIN_SHADER: &str = include_str!("shaders/shader.wgsl");
 
 pub struct Renderer {
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
     render_pipeline: Pipeline,
     camera: Camera,
+    editor_camera: EditorCamera,
     framebuffer: Framebuffer,
     framebuffer_gui_id: TextureId,
     gui_viewport_size: [u32; 2],
+    /// Framebuffer pixel clicked in the viewport, resolved after the next frame is rendered
+    pick_request: Option<[u32; 2]>,
+    /// Area of the framebuffer the last frame was rendered to, in pixels
+    scene_viewport: [f32; 4],
+    gizmo: Gizmo,
     depth_texture: texture::Texture,
     #[cfg(feature = "imgui")]
     gui: Gui,
@@ -89,48 +114,33 @@ impl Renderer {
         };
         surface.configure(&device, &config);
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let camera_bind_group_layout = Camera::create_bind_group_layout(&device);
 
-        let camera = Camera::new(
-            &device,
-            (0.0, 5.0, 10.0),
-            (0.0, 0.0, 0.0),
-            cgmath::Vector3::unit_y(),
-            config.width as f32 / config.height as f32,
-            45.0,
-            0.1,
-            100.0,
-            &camera_bind_group_layout,
-        );
+        let camera = Camera::new(&device, &camera_bind_group_layout);
+        let editor_camera = EditorCamera::new(Point3::new(0.0, 5.0, 10.0), Point3::origin());
 
         let render_pipeline = Pipeline::new(
             &device,
             "Main Renderer",
-            include_str!("shaders/shader.wgsl"),
-            &[ColorTargetState {
-                format: config.format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            }],
+            MAIN_SHADER,
+            &[
+                ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                },
+                ColorTargetState {
+                    format: Framebuffer::ID_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                },
+            ],
             &[&camera_bind_group_layout],
         );
 
         let depth_texture =
             texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-        let framebuffer = Framebuffer::create(&device, 800, 600, config.format, "Main");
+        let framebuffer = Framebuffer::create_with_ids(&device, 800, 600, config.format, "Main");
 
         #[cfg(feature = "imgui")]
         let (mut gui, gui_platform) = init_gui(window, &config.format, &device, &queue);
@@ -138,12 +148,13 @@ impl Renderer {
 
         Self {
             surface,
-            device,
-            queue,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
             config,
 
             render_pipeline,
             camera,
+            editor_camera,
             framebuffer,
             framebuffer_gui_id,
             depth_texture,
@@ -152,6 +163,9 @@ impl Renderer {
             #[cfg(feature = "imgui")]
             gui_platform,
             gui_viewport_size: [0; 2],
+            pick_request: None,
+            scene_viewport: [0.0; 4],
+            gizmo: Gizmo::new(),
         }
     }
 
@@ -161,17 +175,23 @@ impl Renderer {
         self.surface.configure(&self.device, &self.config);
         self.depth_texture =
             texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-        // self.camera.update_aspect(
-        //     &self.queue,
-        //     self.config.width as f32 / self.config.height as f32,
-        // );
     }
 
-    pub(super) fn update(&mut self, dt: Duration, window: &Window, scene: &Scene) {
+    pub(super) fn update(
+        &mut self,
+        dt: Duration,
+        window: &Window,
+        scene: &Scene,
+        scene_file: &mut SceneFile,
+        play_mode: &mut PlayMode,
+    ) {
         //GUI
         {
             let ui = self.gui.update(dt, window, &mut self.gui_platform);
 
+            scene_file.gui(ui);
+            play_mode.gui(ui);
+
             let mut open: bool = true;
             ui.dockspace_over_main_viewport();
             ui.show_demo_window(&mut open);
@@ -179,16 +199,57 @@ impl Renderer {
             //Viewport
             {
                 let _token = ui.push_style_var(imgui::StyleVar::WindowPadding([0.0, 0.0]));
-                if let Some(_) = ui.window("Viewport").begin() {
+                if let Some(_) = ui.window("Viewport").menu_bar(true).begin() {
+                    ui.menu_bar(|| self.gizmo.menu_bar(ui));
+
                     let size = ui.content_region_avail();
+                    self.gui_viewport_size = [size[0] as u32, size[1] as u32];
 
+                    let image_min = ui.cursor_screen_pos();
                     imgui::Image::new(self.framebuffer_gui_id, size).build(ui);
-
-                    self.gui_viewport_size = [size[0] as u32, size[1] as u32];
+                    // Alt + left drag orbits the camera
+                    let clicked = ui.is_item_clicked() && !ui.io().key_alt;
+
+                    let mut gizmo_used = false;
+                    if let Some(object) = scene.selected_object() {
+                        // The image shows the whole framebuffer scaled to the window
+                        let scale = [
+                            size[0] / self.framebuffer.width() as f32,
+                            size[1] / self.framebuffer.height() as f32,
+                        ];
+                        let [x, y, width, height] = self.scene_viewport;
+                        let screen = ViewportScreen {
+                            min: [image_min[0] + x * scale[0], image_min[1] + y * scale[1]],
+                            size: [width * scale[0], height * scale[1]],
+                            view_proj: self.camera.view_projection(),
+                            inverse_view_proj: self.camera.inverse_view_projection(),
+                        };
+                        gizmo_used = self.gizmo.update(ui, &object, &screen);
+                    }
+
+                    if clicked && !gizmo_used && size[0] >= 1.0 && size[1] >= 1.0 {
+                        // The framebuffer is resized to the viewport size before the next frame
+                        let mouse = ui.io().mouse_pos;
+                        let [width, height] = self.gui_viewport_size;
+                        let x = (mouse[0] - image_min[0]) / size[0] * width as f32;
+                        let y = (mouse[1] - image_min[1]) / size[1] * height as f32;
+                        self.pick_request = Some([x as u32, y as u32]);
+                    }
+
+                    self.editor_camera.update(ui, dt);
                 }
             }
 
             scene.gui(ui);
+            scene.record_edits(ui.is_any_item_active() || self.gizmo.is_dragging());
+
+            let stats = self.render_pipeline.stats();
+            ui.window("Statistics").build(|| {
+                ui.text(format!("Objects: {}", stats.objects));
+                ui.text(format!("Culled: {}", stats.culled));
+                ui.text(format!("Visible: {}", stats.objects - stats.culled));
+                ui.text(format!("Draw calls: {}", stats.draw_calls));
+            });
 
             self.gui_platform.end_frame(ui, window);
         }
@@ -218,10 +279,6 @@ impl Renderer {
                 self.gui_viewport_size[1],
                 &self.device,
             ) {
-                self.camera.update_aspect(
-                    &self.queue,
-                    self.gui_viewport_size[0] as f32 / self.gui_viewport_size[1] as f32,
-                );
                 self.gui.update_texture(
                     self.framebuffer_gui_id,
                     self.framebuffer.diffuse(),
@@ -229,39 +286,23 @@ impl Renderer {
                 );
             }
 
-            let bundles = self.render_pipeline.render_scene(
+            self.scene_viewport = apply_camera(
+                &mut self.camera,
+                &self.editor_camera,
+                scene,
+                &self.queue,
+                [self.framebuffer.width(), self.framebuffer.height()],
+            );
+            encode_scene_pass(
+                &mut encoder,
+                &mut self.render_pipeline,
+                &self.framebuffer,
+                &self.camera,
+                self.scene_viewport,
                 scene,
                 &self.device,
                 &self.queue,
-                &[&self.camera.bind_group()],
             );
-
-            let mut scene_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Scene Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.framebuffer.diffuse_view(),
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: self.framebuffer.depth_view(),
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-
-            scene_pass.execute_bundles(&bundles);
         }
 
         //Main Window
@@ -309,6 +350,14 @@ impl Renderer {
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
+
+        if let Some([x, y]) = self.pick_request.take() {
+            match self.framebuffer.read_id(&self.device, &self.queue, x, y) {
+                Ok(id) => scene.select(self.render_pipeline.picked_object(id)),
+                Err(err) => log::warn!("Picking failed: {err:#}"),
+            }
+        }
+
         output.present();
 
         Ok(())
@@ -321,6 +370,89 @@ impl Renderer {
     pub(super) fn queue(&self) -> &Queue {
         &self.queue
     }
+
+    pub(super) fn shared_device(&self) -> Arc<Device> {
+        Arc::clone(&self.device)
+    }
+
+    pub(super) fn shared_queue(&self) -> Arc<Queue> {
+        Arc::clone(&self.queue)
+    }
+}
+
+/// Points `camera` through the first active camera of `scene`, or `editor` if there is none.
+/// Returns the viewport to render to in a framebuffer of `size`, in pixels.
+fn apply_camera(
+    camera: &mut Camera,
+    editor: &EditorCamera,
+    scene: &Scene,
+    queue: &Queue,
+    size: [u32; 2],
+) -> [f32; 4] {
+    let (lens, view) = match scene.active_camera() {
+        Some((lens, world)) => (lens, SceneCamera::view_matrix(&world)),
+        None => (editor.lens.clone(), editor.view_matrix()),
+    };
+    let viewport = lens.viewport_rect(size);
+    let proj = lens.projection_matrix(viewport[2] / viewport[3]);
+    camera.update(queue, view, proj);
+    viewport
+}
+
+/// Records the pass rendering `scene` into the `viewport` of `framebuffer` as seen by `camera`.
+fn encode_scene_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &mut Pipeline,
+    framebuffer: &Framebuffer,
+    camera: &Camera,
+    viewport: [f32; 4],
+    scene: &Scene,
+    device: &Device,
+    queue: &Queue,
+) {
+    let bundles = pipeline.render_scene(scene, camera, device, queue, &[camera.bind_group()]);
+    pipeline.encode_shadow_passes(encoder);
+
+    let mut color_attachments = vec![Some(wgpu::RenderPassColorAttachment {
+        view: framebuffer.diffuse_view(),
+        resolve_target: None,
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            }),
+            store: true,
+        },
+    })];
+    if let Some(ids) = framebuffer.ids_view() {
+        color_attachments.push(Some(wgpu::RenderPassColorAttachment {
+            view: ids,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: true,
+            },
+        }));
+    }
+
+    let mut scene_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Scene Render Pass"),
+        color_attachments: &color_attachments,
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: framebuffer.depth_view(),
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    });
+
+    let [x, y, width, height] = viewport;
+    scene_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+    scene_pass.execute_bundles(bundles.iter().map(|b| b.as_ref()));
 }
 
 // pub(super) struct Instance {
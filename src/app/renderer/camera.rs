@@ -1,39 +1,16 @@
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Transform};
 use wgpu::{util::DeviceExt, BindGroup, Device};
 
+/// View and projection the scene is rendered with, shared with shaders as a uniform.
 pub struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
-    aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
-
     uniform: CameraUniform,
     buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
 }
 
 impl Camera {
-    pub fn new<P: Into<cgmath::Point3<f32>>, V: Into<cgmath::Vector3<f32>>>(
-        device: &Device,
-        eye: P,
-        target: P,
-        up: V,
-        aspect: f32,
-        fovy: f32,
-        znear: f32,
-        zfar: f32,
-        camera_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Camera {
-        let eye = eye.into();
-        let target = target.into();
-        let up = up.into();
-
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(Camera::build_view_projection_matrix(
-            eye, target, up, aspect, fovy, znear, zfar,
-        ));
+    pub fn new(device: &Device, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Camera {
+        let camera_uniform = CameraUniform::new();
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -49,71 +26,107 @@ impl Camera {
             label: Some("camera_bind_group"),
         });
         Self {
-            aspect,
             bind_group: camera_bind_group,
             buffer: camera_buffer,
             uniform: camera_uniform,
-            eye,
-            target,
-            fovy,
-            zfar,
-            znear,
-            up,
         }
     }
 
+    pub fn create_bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        })
+    }
+
     pub fn bind_group(&self) -> &BindGroup {
         &self.bind_group
     }
-    pub fn update_aspect(&mut self, queue: &wgpu::Queue, aspect: f32) {
-        self.aspect = aspect;
-        self.uniform
-            .update_view_proj(Camera::build_view_projection_matrix(
-                self.eye,
-                self.target,
-                self.up,
-                self.aspect,
-                self.fovy,
-                self.znear,
-                self.zfar,
-            ));
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        Matrix4::from(self.uniform.view_proj)
+    }
+    pub fn inverse_view_projection(&self) -> Matrix4<f32> {
+        Matrix4::from(self.uniform.inv_view_proj)
+    }
+    /// Distance of the near plane from the eye.
+    pub fn znear(&self) -> f32 {
+        -self.unproject(Point3::new(0.0, 0.0, 0.0)).z
+    }
+    /// World space corners of the view frustum between the distances `near` and `far`.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Point3<f32>; 8] {
+        let inverse_view = Matrix4::from(self.uniform.inv_view);
+        let mut corners = [Point3::origin(); 8];
+        for (i, [x, y]) in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]]
+            .into_iter()
+            .enumerate()
+        {
+            // Walk along the edge between the near and far plane, this works for any projection
+            let start = self.unproject(Point3::new(x, y, 0.0));
+            let end = self.unproject(Point3::new(x, y, 1.0));
+            for (j, distance) in [near, far].into_iter().enumerate() {
+                let t = (distance + start.z) / (start.z - end.z);
+                corners[j * 4 + i] = inverse_view.transform_point(start + (end - start) * t);
+            }
+        }
+        corners
+    }
+    /// Sets the view and projection and uploads them.
+    pub fn update(&mut self, queue: &wgpu::Queue, view: Matrix4<f32>, proj: Matrix4<f32>) {
+        self.uniform.update(view, proj);
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
     }
-}
-
-impl Camera {
-    fn build_view_projection_matrix(
-        eye: cgmath::Point3<f32>,
-        target: cgmath::Point3<f32>,
-        up: cgmath::Vector3<f32>,
-        aspect: f32,
-        fovy: f32,
-        znear: f32,
-        zfar: f32,
-    ) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(eye, target, up);
-        let proj = cgmath::perspective(cgmath::Deg(fovy), aspect, znear, zfar);
 
-        OPENGL_TO_WGPU_MATRIX * proj * view
+    /// Point in normalized device coordinates to view space.
+    fn unproject(&self, point: Point3<f32>) -> Point3<f32> {
+        Matrix4::from(self.uniform.inv_proj).transform_point(point)
     }
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    inv_view: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
     fn new() -> Self {
-        use cgmath::SquareMatrix;
+        let identity = Matrix4::identity().into();
         Self {
-            view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0, 0.0, 0.0, 1.0],
+            view_proj: identity,
+            view: identity,
+            proj: identity,
+            inv_view: identity,
+            inv_proj: identity,
+            inv_view_proj: identity,
         }
     }
 
-    fn update_view_proj(&mut self, camera_matrix: cgmath::Matrix4<f32>) {
-        self.view_proj = camera_matrix.into();
+    fn update(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>) {
+        let inv_view = view.invert().unwrap_or(Matrix4::identity());
+        let inv_proj = proj.invert().unwrap_or(Matrix4::identity());
+        self.view_position = inv_view.w.into();
+        self.view_proj = (proj * view).into();
+        self.view = view.into();
+        self.proj = proj.into();
+        self.inv_view = inv_view.into();
+        self.inv_proj = inv_proj.into();
+        self.inv_view_proj = (inv_view * inv_proj).into();
     }
 }
 
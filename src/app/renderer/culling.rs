@@ -0,0 +1,168 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Transform, Vector3, Vector4};
+
+use super::model::Bounds;
+
+/// View frustum as six inward facing planes, `xyz` is the normal and `w` the distance.
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a wgpu style view projection matrix (depth from 0 to 1).
+    pub fn from_matrix(view_proj: &Matrix4<f32>) -> Frustum {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| {
+            let length = p.truncate().magnitude();
+            if length > 0.0 {
+                p / length
+            } else {
+                p
+            }
+        });
+        Frustum { planes }
+    }
+
+    /// Conservative test of `bounds` transformed by `world`, false means surely outside.
+    pub fn intersects(&self, bounds: &Bounds, world: &Matrix4<f32>) -> bool {
+        // Cheap sphere test first, the box is only needed if the sphere straddles a plane
+        let center = world.transform_point(bounds.center);
+        let scale = [world.x, world.y, world.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        let radius = bounds.radius * scale;
+        let mut straddles = false;
+        for plane in &self.planes {
+            let distance = plane.truncate().dot(center.to_vec()) + plane.w;
+            if distance < -radius {
+                return false;
+            }
+            straddles |= distance < radius;
+        }
+        if !straddles {
+            return true;
+        }
+
+        let (min, max) = world_aabb(bounds, world);
+        self.planes.iter().all(|plane| {
+            // Corner furthest along the plane normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// Axis aligned box around the transformed box of `bounds` (Arvo's method).
+fn world_aabb(bounds: &Bounds, world: &Matrix4<f32>) -> (Point3<f32>, Point3<f32>) {
+    let translation = world.w.truncate();
+    let mut min = Point3::new(translation.x, translation.y, translation.z);
+    let mut max = min;
+    for row in 0..3 {
+        for column in 0..3 {
+            let a = world[column][row] * bounds.min[column];
+            let b = world[column][row] * bounds.max[column];
+            min[row] += a.min(b);
+            max[row] += a.max(b);
+        }
+    }
+    (min, max)
+}
+
+/// Counts of the last rendered frame, shown in the editor.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RenderStats {
+    pub objects: usize,
+    pub culled: usize,
+    pub draw_calls: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{vec3, Deg};
+
+    use super::*;
+    use crate::app::scene::component::Camera;
+
+    /// Unit box around the origin, corners at -1 and 1.
+    fn unit_box() -> Bounds {
+        Bounds::from_points([[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]].into_iter())
+    }
+
+    /// Camera at the origin looking down -Z, 90 degrees wide, from 1 to 10.
+    fn perspective() -> Frustum {
+        Frustum::from_matrix(&Camera::perspective(90.0, 1.0, 10.0).projection_matrix(1.0))
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> Matrix4<f32> {
+        Matrix4::from_translation(vec3(x, y, z))
+    }
+
+    #[test]
+    fn box_inside_is_visible() {
+        assert!(perspective().intersects(&unit_box(), &at(0.0, 0.0, -5.0)));
+    }
+
+    #[test]
+    fn box_outside_any_plane_is_culled() {
+        let frustum = perspective();
+        // Left, right, bottom, top, near and far
+        for world in [
+            at(-20.0, 0.0, -5.0),
+            at(20.0, 0.0, -5.0),
+            at(0.0, -20.0, -5.0),
+            at(0.0, 20.0, -5.0),
+            at(0.0, 0.0, 5.0),
+            at(0.0, 0.0, -20.0),
+        ] {
+            assert!(!frustum.intersects(&unit_box(), &world), "{world:?}");
+        }
+    }
+
+    #[test]
+    fn box_straddling_any_plane_is_visible() {
+        let frustum = perspective();
+        // At z = -5 the side planes are 5 away from the axis
+        for world in [
+            at(-5.0, 0.0, -5.0),
+            at(5.0, 0.0, -5.0),
+            at(0.0, -5.0, -5.0),
+            at(0.0, 5.0, -5.0),
+            at(0.0, 0.0, -1.0),
+            at(0.0, 0.0, -10.0),
+        ] {
+            assert!(frustum.intersects(&unit_box(), &world), "{world:?}");
+        }
+    }
+
+    #[test]
+    fn box_outside_but_within_its_sphere_is_culled() {
+        // The bounding sphere reaches past the far plane, the box does not
+        assert!(!perspective().intersects(&unit_box(), &at(0.0, 0.0, -11.5)));
+    }
+
+    #[test]
+    fn world_matrix_scales_and_rotates_the_box() {
+        let frustum = perspective();
+        let turned = Matrix4::from_angle_z(Deg(45.0)) * Matrix4::from_scale(3.0);
+        assert!(!frustum.intersects(&unit_box(), &at(8.0, 0.0, -5.0)));
+        assert!(frustum.intersects(&unit_box(), &(at(8.0, 0.0, -5.0) * turned)));
+        assert!(!frustum.intersects(&unit_box(), &(at(0.0, 0.0, -14.0) * turned)));
+        assert!(frustum.intersects(&unit_box(), &(at(0.0, 0.0, -12.0) * turned)));
+    }
+
+    #[test]
+    fn orthographic_frustum_is_a_box() {
+        let camera = Camera::orthographic(5.0, 1.0, 100.0);
+        let frustum = Frustum::from_matrix(&camera.projection_matrix(1.0));
+        assert!(frustum.intersects(&unit_box(), &at(0.0, 0.0, -90.0)));
+        assert!(frustum.intersects(&unit_box(), &at(5.5, 0.0, -90.0)));
+        assert!(!frustum.intersects(&unit_box(), &at(7.0, 0.0, -90.0)));
+        assert!(!frustum.intersects(&unit_box(), &at(0.0, -7.0, -2.0)));
+        assert!(!frustum.intersects(&unit_box(), &at(0.0, 0.0, 5.0)));
+        assert!(!frustum.intersects(&unit_box(), &at(0.0, 0.0, -102.0)));
+    }
+}
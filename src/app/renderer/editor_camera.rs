@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use imgui::{Key, MouseButton, Ui};
+
+use crate::app::scene::component::Camera;
+
+const ORBIT_SPEED: f32 = 0.3;
+const LOOK_SPEED: f32 = 0.2;
+const PAN_SPEED: f32 = 0.0015;
+const ZOOM_STEP: f32 = 0.1;
+const FLY_SPEED: f32 = 5.0;
+const FAST_MULTIPLIER: f32 = 4.0;
+const MIN_DISTANCE: f32 = 0.05;
+const MAX_PITCH: f32 = 89.0;
+
+/// Orbit/fly controller for the editor viewport camera.
+///
+/// Alt + left drag orbits around the target, middle drag pans, the wheel zooms and
+/// holding the right button looks around while WASD/QE fly (shift to go faster).
+pub struct EditorCamera {
+    target: Point3<f32>,
+    distance: f32,
+    /// Degrees around the y axis, 0 looks down -z.
+    yaw: f32,
+    /// Degrees above the horizon of the eye as seen from the target.
+    pitch: f32,
+    /// Projection settings used while no scene camera is active
+    pub lens: Camera,
+}
+
+impl EditorCamera {
+    pub fn new(eye: Point3<f32>, target: Point3<f32>) -> EditorCamera {
+        let offset = eye - target;
+        let distance = offset.magnitude().max(MIN_DISTANCE);
+        EditorCamera {
+            target,
+            distance,
+            yaw: offset.x.atan2(offset.z).to_degrees(),
+            pitch: (offset.y / distance).clamp(-1.0, 1.0).asin().to_degrees(),
+            lens: Camera::new(),
+        }
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.target + self.offset()
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(self.eye(), self.target, Vector3::unit_y())
+    }
+
+    /// Applies this frame's input, call inside the viewport window.
+    pub fn update(&mut self, ui: &Ui, dt: Duration) {
+        if !ui.is_window_hovered() && !ui.is_window_focused() {
+            return;
+        }
+        let io = ui.io();
+        let [dx, dy] = io.mouse_delta;
+
+        if ui.is_mouse_down(MouseButton::Right) {
+            // Look around the eye
+            let eye = self.eye();
+            self.rotate(-dx * LOOK_SPEED, dy * LOOK_SPEED);
+            self.target = eye - self.offset();
+
+            let mut direction = Vector3::new(0.0, 0.0, 0.0);
+            let (forward, right) = (self.forward(), self.right());
+            for (key, axis) in [
+                (Key::W, forward),
+                (Key::S, -forward),
+                (Key::D, right),
+                (Key::A, -right),
+                (Key::E, Vector3::unit_y()),
+                (Key::Q, -Vector3::unit_y()),
+            ] {
+                if ui.is_key_down(key) {
+                    direction += axis;
+                }
+            }
+            if direction.magnitude2() > 0.0 {
+                let speed = if io.key_shift {
+                    FLY_SPEED * FAST_MULTIPLIER
+                } else {
+                    FLY_SPEED
+                };
+                self.target += direction.normalize() * speed * dt.as_secs_f32();
+            }
+        } else if ui.is_mouse_down(MouseButton::Middle) {
+            let scale = self.distance * PAN_SPEED;
+            let up = self.right().cross(self.forward());
+            self.target += (-self.right() * dx + up * dy) * scale;
+        } else if io.key_alt && ui.is_mouse_down(MouseButton::Left) {
+            self.rotate(-dx * ORBIT_SPEED, dy * ORBIT_SPEED);
+        }
+
+        if io.mouse_wheel != 0.0 && ui.is_window_hovered() {
+            self.distance = (self.distance * (1.0 - io.mouse_wheel * ZOOM_STEP)).max(MIN_DISTANCE);
+        }
+    }
+
+    fn rotate(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % 360.0;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    fn offset(&self) -> Vector3<f32> {
+        let (yaw, pitch) = (self.yaw.to_radians(), self.pitch.to_radians());
+        Vector3::new(
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        ) * self.distance
+    }
+
+    fn forward(&self) -> Vector3<f32> {
+        -self.offset().normalize()
+    }
+
+    fn right(&self) -> Vector3<f32> {
+        self.forward().cross(Vector3::unit_y()).normalize()
+    }
+}
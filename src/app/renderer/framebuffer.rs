@@ -1,8 +1,12 @@
+use anyhow::{bail, Context};
+
 use crate::app::Texture;
 
 pub struct Framebuffer {
     diffuse: Texture,
     depth: Texture,
+    /// Object ids written by picking pipelines, 0 where nothing was drawn
+    ids: Option<Texture>,
     width: u32,
     height: u32,
     diffuse_format: wgpu::TextureFormat,
@@ -10,12 +14,36 @@ pub struct Framebuffer {
 }
 
 impl Framebuffer {
+    pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
     pub fn create(
         device: &wgpu::Device,
         width: u32,
         height: u32,
         format: wgpu::TextureFormat,
         name: &str,
+    ) -> Self {
+        Self::create_with(device, width, height, format, false, name)
+    }
+
+    /// Framebuffer with an additional `ID_FORMAT` target for object picking.
+    pub fn create_with_ids(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        name: &str,
+    ) -> Self {
+        Self::create_with(device, width, height, format, true, name)
+    }
+
+    fn create_with(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        ids: bool,
+        name: &str,
     ) -> Self {
         let diffuse = Texture::create_texture(
             device,
@@ -29,10 +57,19 @@ impl Framebuffer {
             Texture::DEPTH_FORMAT,
             &format!("Framebuffer depth: {name}"),
         );
+        let ids = ids.then(|| {
+            Texture::create_texture(
+                device,
+                (width, height),
+                Self::ID_FORMAT,
+                &format!("Framebuffer ids: {name}"),
+            )
+        });
 
         Self {
             diffuse,
             depth,
+            ids,
             width,
             height,
             diffuse_format: format,
@@ -46,6 +83,9 @@ impl Framebuffer {
     pub fn depth_view(&self) -> &wgpu::TextureView {
         self.depth.view()
     }
+    pub fn ids_view(&self) -> Option<&wgpu::TextureView> {
+        self.ids.as_ref().map(|ids| ids.view())
+    }
     #[allow(dead_code)]
     pub fn depth(&self) -> &Texture {
         &self.depth
@@ -53,11 +93,9 @@ impl Framebuffer {
     pub fn diffuse(&self) -> &Texture {
         &self.diffuse
     }
-    #[allow(dead_code)]
     pub fn width(&self) -> u32 {
         self.width
     }
-    #[allow(dead_code)]
     pub fn height(&self) -> u32 {
         self.height
     }
@@ -76,6 +114,14 @@ impl Framebuffer {
                 Texture::DEPTH_FORMAT,
                 &format!("Framebuffer depth: {}", self.name),
             );
+            if self.ids.is_some() {
+                self.ids = Some(Texture::create_texture(
+                    device,
+                    (width, height),
+                    Self::ID_FORMAT,
+                    &format!("Framebuffer ids: {}", self.name),
+                ));
+            }
             self.width = width;
             self.height = height;
             true
@@ -83,4 +129,147 @@ impl Framebuffer {
             false
         }
     }
+
+    /// Reads the object id at pixel `x`, `y`, blocking until the gpu finished.
+    pub fn read_id(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        x: u32,
+        y: u32,
+    ) -> anyhow::Result<u32> {
+        let ids = self
+            .ids
+            .as_ref()
+            .with_context(|| format!("Framebuffer {} has no id target", self.name))?;
+        if x >= self.width || y >= self.height {
+            bail!(
+                "Pixel {x}, {y} is outside of the {}x{} framebuffer",
+                self.width,
+                self.height
+            );
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Framebuffer id readback: {}", self.name)),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let texture = ids.texture();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Framebuffer Id Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let id = bytemuck::pod_read_unaligned::<u32>(&slice.get_mapped_range()[..4]);
+        buffer.unmap();
+        Ok(id)
+    }
+
+    /// Copies the diffuse target back to the cpu, blocking until the gpu finished.
+    pub fn read_diffuse(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let swizzle = match self.diffuse_format.remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            format => bail!("Reading back {format:?} framebuffers is not supported"),
+        };
+
+        // Rows of a texture copy have to be aligned
+        let unpadded_bytes_per_row = self.width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Framebuffer readback: {}", self.name)),
+            size: (padded_bytes_per_row * self.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let texture = self.diffuse.texture();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Framebuffer Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if swizzle {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .context("Framebuffer data does not match its size")
+    }
 }
@@ -0,0 +1,622 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use cgmath::Quaternion;
+use gltf::{
+    image::Source,
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
+use wgpu::util::DeviceExt;
+
+use crate::app::{
+    assets::{AssetDatabase, AssetHandle, AssetLocation},
+    scene::component::Transform,
+};
+
+use super::{
+    mesh::MeshVertex,
+    model::{
+        load_binary, resource_path, Bounds, Material, MaterialFactors, Mesh, Model, ModelNode,
+    },
+    texture::Texture,
+};
+
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    asset_databse: AssetDatabase,
+) -> anyhow::Result<Model> {
+    let (document, buffers) = import(&load_binary(file_name).await?, file_name)?;
+
+    let mut materials = Vec::new();
+    for material in document.materials() {
+        let ident = material_ident(&material);
+        let pbr = material.pbr_metallic_roughness();
+        let mut loaded = Material::new(ident.clone(), &asset_databse)?;
+        loaded.factors = MaterialFactors {
+            base_color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: material.emissive_factor(),
+            normal_scale: material.normal_texture().map_or(1.0, |n| n.scale()),
+            occlusion_strength: material.occlusion_texture().map_or(1.0, |o| o.strength()),
+        };
+
+        let maps = [
+            (
+                &mut loaded.base_color_texture,
+                pbr.base_color_texture().map(|i| i.texture()),
+                Texture::SRGB_FORMAT,
+            ),
+            (
+                &mut loaded.normal_texture,
+                material.normal_texture().map(|n| n.texture()),
+                Texture::LINEAR_FORMAT,
+            ),
+            (
+                &mut loaded.metallic_roughness_texture,
+                pbr.metallic_roughness_texture().map(|i| i.texture()),
+                Texture::LINEAR_FORMAT,
+            ),
+            (
+                &mut loaded.occlusion_texture,
+                material.occlusion_texture().map(|o| o.texture()),
+                Texture::LINEAR_FORMAT,
+            ),
+            (
+                &mut loaded.emissive_texture,
+                material.emissive_texture().map(|i| i.texture()),
+                Texture::SRGB_FORMAT,
+            ),
+        ];
+        warn_on_differing_transforms(&material, &ident, file_name);
+        for (slot, texture, format) in maps {
+            if let Some(texture) = texture {
+                *slot = load_texture(
+                    texture,
+                    format,
+                    file_name,
+                    &buffers,
+                    device,
+                    queue,
+                    &asset_databse,
+                )
+                .await?;
+            }
+        }
+
+        materials.push(asset_databse.load_material(
+            AssetLocation::Resource {
+                path: file_name.to_string(),
+                in_file_ident: Some(ident),
+            },
+            loaded,
+        ));
+    }
+
+    let primitives = read_meshes(&document, &buffers, file_name)?;
+    let nodes = read_nodes(&document, &primitives, file_name)?;
+
+    // Primitives without a material use a default one appended after the file's materials
+    let default_material = materials.len();
+    if primitives.iter().flatten().any(|p| p.material.is_none()) {
+        materials.push(asset_databse.load_material(
+            AssetLocation::Resource {
+                path: file_name.to_string(),
+                in_file_ident: Some("default_material".to_string()),
+            },
+            Material::new("default_material", &asset_databse)?,
+        ));
+    }
+
+    let mut meshes = Vec::new();
+    for primitive in primitives.into_iter().flatten() {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", primitive.ident)),
+            contents: bytemuck::cast_slice(&primitive.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", primitive.ident)),
+            contents: bytemuck::cast_slice(&primitive.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        meshes.push(asset_databse.load_mesh(
+            AssetLocation::Resource {
+                path: file_name.to_string(),
+                in_file_ident: Some(primitive.ident.clone()),
+            },
+            Mesh::new(
+                primitive.ident,
+                vertex_buffer,
+                index_buffer,
+                primitive.indices.len() as u32,
+                primitive.material.unwrap_or(default_material),
+                Bounds::from_points(primitive.vertices.iter().map(|v| v.position)),
+            ),
+        ));
+    }
+
+    Ok(Model {
+        meshes,
+        materials,
+        nodes,
+    })
+}
+
+/// Parses the glTF file read from `file_name` and loads its buffers.
+fn import(
+    data: &[u8],
+    file_name: &str,
+) -> anyhow::Result<(gltf::Document, Vec<gltf::buffer::Data>)> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(data)?;
+    // The importer panics on relative uris that do not decode
+    for buffer in document.buffers() {
+        if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+            if !uri.contains(':') && urlencoding::decode(uri).is_err() {
+                bail!("Invalid buffer uri {uri} in {file_name}");
+            }
+        }
+    }
+    let base = resource_path(file_name);
+    let buffers = gltf::import_buffers(&document, base.parent(), blob)
+        .with_context(|| format!("Failed to load buffers of {file_name}"))?;
+    Ok((document, buffers))
+}
+
+/// Name of the material in asset locations, unnamed ones go by their index.
+fn material_ident(material: &gltf::Material) -> String {
+    material.name().map_or_else(
+        || format!("material_{}", material.index().unwrap_or_default()),
+        str::to_string,
+    )
+}
+
+/// Vertices and indices of a triangle primitive, with the base color transform baked into
+/// the uvs.
+struct Primitive {
+    /// "mesh name/primitive index"
+    ident: String,
+    vertices: Vec<MeshVertex>,
+    indices: Vec<u32>,
+    material: Option<usize>,
+}
+
+/// The primitives of every mesh, by mesh index. Primitives that are not triangles are skipped.
+fn read_meshes(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    file_name: &str,
+) -> anyhow::Result<Vec<Vec<Primitive>>> {
+    let mut meshes = Vec::new();
+    for mesh in document.meshes() {
+        let mesh_name = mesh
+            .name()
+            .map_or_else(|| format!("mesh_{}", mesh.index()), str::to_string);
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "Skipping primitive {} of {mesh_name} in {file_name}: only triangles are supported",
+                    primitive.index()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&*buffers[buffer.index()]));
+            let positions = reader
+                .read_positions()
+                .with_context(|| {
+                    format!(
+                        "Primitive {} of {mesh_name} has no positions",
+                        primitive.index()
+                    )
+                })?
+                .collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .map_or_else(|| vec![[0.0; 3]; positions.len()], |n| n.collect());
+            let mut tex_coords = reader.read_tex_coords(0).map_or_else(
+                || vec![[0.0; 2]; positions.len()],
+                |t| t.into_f32().collect(),
+            );
+            let indices = reader.read_indices().map_or_else(
+                || (0..positions.len() as u32).collect::<Vec<_>>(),
+                |i| i.into_u32().collect(),
+            );
+
+            // All maps share one set of uvs, so the base color transform is baked into them
+            if let Some(info) = primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_texture()
+            {
+                let transform = UvTransform::of_info(&info);
+                if transform != UvTransform::IDENTITY {
+                    for uv in tex_coords.iter_mut() {
+                        *uv = transform.apply(*uv);
+                    }
+                }
+            }
+
+            let vertices = positions
+                .iter()
+                .zip(normals.iter())
+                .zip(tex_coords.iter())
+                .map(|((position, normal), tex_coords)| MeshVertex {
+                    position: *position,
+                    tex_coords: *tex_coords,
+                    normal: *normal,
+                })
+                .collect::<Vec<_>>();
+
+            primitives.push(Primitive {
+                ident: format!("{mesh_name}/{}", primitive.index()),
+                vertices,
+                indices,
+                material: primitive.material().index(),
+            });
+        }
+        meshes.push(primitives);
+    }
+    Ok(meshes)
+}
+
+/// Node tree of the default scene. Nodes refer to the primitives of `meshes` by their
+/// position with all meshes' primitives laid out in order.
+fn read_nodes(
+    document: &gltf::Document,
+    meshes: &[Vec<Primitive>],
+    file_name: &str,
+) -> anyhow::Result<Vec<ModelNode>> {
+    let mut next = 0;
+    let mesh_primitives = meshes
+        .iter()
+        .map(|primitives| {
+            next += primitives.len();
+            (next - primitives.len()..next).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .with_context(|| format!("{file_name} contains no scene"))?;
+    Ok(scene
+        .nodes()
+        .map(|node| load_node(&node, &mesh_primitives))
+        .collect())
+}
+
+/// A `KHR_texture_transform`, maps without one use the identity.
+#[derive(Clone, Copy, PartialEq)]
+struct UvTransform {
+    offset: [f32; 2],
+    rotation: f32,
+    scale: [f32; 2],
+}
+
+impl UvTransform {
+    const IDENTITY: UvTransform = UvTransform {
+        offset: [0.0, 0.0],
+        rotation: 0.0,
+        scale: [1.0, 1.0],
+    };
+
+    fn of_info(info: &gltf::texture::Info) -> UvTransform {
+        info.texture_transform()
+            .map_or(Self::IDENTITY, |t| UvTransform {
+                offset: t.offset(),
+                rotation: t.rotation(),
+                scale: t.scale(),
+            })
+    }
+
+    /// The normal and occlusion maps only expose the extension as json.
+    fn of_json(value: Option<&gltf::json::Value>) -> UvTransform {
+        let Some(value) = value else {
+            return Self::IDENTITY;
+        };
+        let pair = |key, default: [f32; 2]| match value.get(key).and_then(|v| v.as_array()) {
+            Some(values) => [0, 1].map(|i| {
+                values
+                    .get(i)
+                    .and_then(|v| v.as_f64())
+                    .map_or(default[i], |v| v as f32)
+            }),
+            None => default,
+        };
+        UvTransform {
+            offset: pair("offset", Self::IDENTITY.offset),
+            rotation: value
+                .get("rotation")
+                .and_then(|v| v.as_f64())
+                .map_or(0.0, |v| v as f32),
+            scale: pair("scale", Self::IDENTITY.scale),
+        }
+    }
+
+    fn apply(&self, [u, v]: [f32; 2]) -> [f32; 2] {
+        let (u, v) = (u * self.scale[0], v * self.scale[1]);
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            cos * u + sin * v + self.offset[0],
+            -sin * u + cos * v + self.offset[1],
+        ]
+    }
+}
+
+/// Only the base color transform is baked into the shared uvs, other maps that want a different
+/// one are drawn with it anyway.
+fn warn_on_differing_transforms(material: &gltf::Material, ident: &str, file_name: &str) {
+    const EXTENSION: &str = "KHR_texture_transform";
+    let pbr = material.pbr_metallic_roughness();
+    let Some(base_color) = pbr.base_color_texture() else {
+        // Without a base color map no transform is baked
+        return;
+    };
+    let expected = UvTransform::of_info(&base_color);
+    let maps = [
+        (
+            "normal",
+            material
+                .normal_texture()
+                .map(|n| UvTransform::of_json(n.extension_value(EXTENSION))),
+        ),
+        (
+            "metallic-roughness",
+            pbr.metallic_roughness_texture()
+                .map(|i| UvTransform::of_info(&i)),
+        ),
+        (
+            "occlusion",
+            material
+                .occlusion_texture()
+                .map(|o| UvTransform::of_json(o.extension_value(EXTENSION))),
+        ),
+        (
+            "emissive",
+            material
+                .emissive_texture()
+                .map(|i| UvTransform::of_info(&i)),
+        ),
+    ];
+    for (map, transform) in maps {
+        if transform.is_some_and(|t| t != expected) {
+            log::warn!(
+                "The {map} map of material {ident} in {file_name} has another {EXTENSION} than its base color map, it is drawn with the base color transform"
+            );
+        }
+    }
+}
+
+fn load_node(node: &gltf::Node, mesh_primitives: &[Vec<usize>]) -> ModelNode {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+
+    ModelNode {
+        name: node
+            .name()
+            .map_or_else(|| format!("Node {}", node.index()), str::to_string),
+        transform: Transform::from_parts(
+            translation.into(),
+            Quaternion::new(w, x, y, z),
+            scale.into(),
+        ),
+        meshes: node
+            .mesh()
+            .map_or_else(Vec::new, |m| mesh_primitives[m.index()].clone()),
+        children: node
+            .children()
+            .map(|child| load_node(&child, mesh_primitives))
+            .collect(),
+    }
+}
+
+async fn load_texture(
+    texture: gltf::Texture<'_>,
+    format: wgpu::TextureFormat,
+    file_name: &str,
+    buffers: &[gltf::buffer::Data],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    asset_databse: &AssetDatabase,
+) -> anyhow::Result<AssetHandle<Texture>> {
+    let sampler = sampler_descriptor(&texture.sampler());
+    let variant = texture_variant(format, &sampler);
+
+    let image = texture.source();
+    match image.source() {
+        Source::Uri { uri, .. } => {
+            if uri.starts_with("data:") {
+                bail!("Embedded data uris are not supported ({file_name})");
+            }
+            let uri = urlencoding::decode(uri)
+                .with_context(|| format!("Invalid image uri {uri} in {file_name}"))?;
+            // Uris are relative to the gltf file
+            let path = match Path::new(file_name).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => format!("{}/{uri}", dir.display()),
+                _ => uri.to_string(),
+            };
+            let location = AssetLocation::Resource {
+                path: path.clone(),
+                in_file_ident: Some(variant),
+            };
+            if let Some(handle) = asset_databse.texture(location.clone()) {
+                return Ok(handle);
+            }
+            let texture = Texture::load_texture_with_format(&path, device, queue, format)
+                .await?
+                .with_sampler(device, &sampler);
+            Ok(asset_databse.load_texture(location, texture))
+        }
+        Source::View { view, .. } => {
+            let location = AssetLocation::Resource {
+                path: file_name.to_string(),
+                in_file_ident: Some(format!(
+                    "{}/{variant}",
+                    image
+                        .name()
+                        .map_or_else(|| format!("image_{}", image.index()), str::to_string)
+                )),
+            };
+            if let Some(handle) = asset_databse.texture(location.clone()) {
+                return Ok(handle);
+            }
+            let buffer = &buffers[view.buffer().index()];
+            let bytes = &buffer[view.offset()..view.offset() + view.length()];
+            let texture = Texture::from_bytes_with_format(
+                device,
+                queue,
+                bytes,
+                &location.to_ident(),
+                format,
+            )?
+            .with_sampler(device, &sampler);
+            Ok(asset_databse.load_texture(location, texture))
+        }
+    }
+}
+
+/// Part of the location of a loaded image. An image used with another format or sampler is
+/// decoded again, so it is not shared between those uses.
+fn texture_variant(format: wgpu::TextureFormat, sampler: &wgpu::SamplerDescriptor) -> String {
+    format!(
+        "{format:?}/{:?}/{:?}/{:?}/{:?}/{:?}",
+        sampler.address_mode_u,
+        sampler.address_mode_v,
+        sampler.mag_filter,
+        sampler.min_filter,
+        sampler.mipmap_filter
+    )
+}
+
+/// The glTF sampler as wgpu sees it, filters the file leaves open keep the engine defaults.
+fn sampler_descriptor(sampler: &gltf::texture::Sampler) -> wgpu::SamplerDescriptor<'static> {
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+    };
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) | None => {
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest)
+        }
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => {
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest)
+        }
+        Some(MinFilter::NearestMipmapLinear) => {
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear)
+        }
+        Some(MinFilter::LinearMipmapLinear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear),
+    };
+    wgpu::SamplerDescriptor {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::vec3;
+
+    use super::*;
+
+    /// Two named and one unnamed node over a mesh with a textured, a point and a plain
+    /// primitive and an unnamed mesh, the buffer embedded as a data uri.
+    const FIXTURE: &str = "tests/primitives.gltf";
+
+    fn fixture() -> (gltf::Document, Vec<Vec<Primitive>>) {
+        let data = std::fs::read(resource_path(FIXTURE)).unwrap();
+        let (document, buffers) = import(&data, FIXTURE).unwrap();
+        let meshes = read_meshes(&document, &buffers, FIXTURE).unwrap();
+        (document, meshes)
+    }
+
+    fn uvs(primitive: &Primitive) -> Vec<[f32; 2]> {
+        primitive.vertices.iter().map(|v| v.tex_coords).collect()
+    }
+
+    #[test]
+    fn primitives_and_materials_are_named_for_their_locations() {
+        let (document, meshes) = fixture();
+        let idents = meshes
+            .iter()
+            .map(|primitives| primitives.iter().map(|p| p.ident.as_str()).collect())
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(idents, [vec!["Triangle/0", "Triangle/2"], vec!["mesh_1/0"]]);
+
+        let materials = meshes.iter().flatten().map(|p| p.material);
+        assert_eq!(materials.collect::<Vec<_>>(), [Some(0), Some(1), None]);
+        let names = document.materials().map(|m| material_ident(&m));
+        assert_eq!(names.collect::<Vec<_>>(), ["Textured", "material_1"]);
+    }
+
+    #[test]
+    fn node_tree_becomes_model_nodes() {
+        let (document, meshes) = fixture();
+        let nodes = read_nodes(&document, &meshes, FIXTURE).unwrap();
+        let [root] = &nodes[..] else {
+            panic!("Expected one root, got {}", nodes.len());
+        };
+        assert_eq!(root.name, "Root");
+        assert_eq!(root.transform.position(), vec3(1.0, 2.0, 3.0));
+        assert!(root.meshes.is_empty());
+
+        let [child, unnamed] = &root.children[..] else {
+            panic!("Expected two children, got {}", root.children.len());
+        };
+        assert_eq!(child.name, "Child");
+        assert_eq!(child.meshes, [0, 1]);
+        assert_eq!(unnamed.name, "Node 2");
+        assert_eq!(unnamed.meshes, [2]);
+        assert_eq!(unnamed.transform.scale(), vec3(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn base_color_transform_is_baked_into_uvs() {
+        let (_, meshes) = fixture();
+        // Scaled by 2, then offset by half in u
+        assert_eq!(uvs(&meshes[0][0]), [[0.5, 0.0], [2.5, 0.0], [0.5, 2.0]]);
+        assert_eq!(uvs(&meshes[0][1]), [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        assert_eq!(uvs(&meshes[1][0]), [[0.0, 0.0]; 3]);
+    }
+
+    #[test]
+    fn broken_data_uri_buffer_is_an_error() {
+        let gltf = r#"{
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 4, "uri": "data:application/octet-stream;base64,@@@@"}]
+        }"#;
+        let error = import(gltf.as_bytes(), "broken.gltf").err().unwrap();
+        assert!(
+            format!("{error:#}").contains("Failed to load buffers of broken.gltf"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn undecodable_buffer_uri_is_an_error() {
+        let gltf = r#"{
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 4, "uri": "buffer%FF.bin"}]
+        }"#;
+        let error = import(gltf.as_bytes(), "broken.gltf").err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid buffer uri buffer%FF.bin"),
+            "{error:#}"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use cgmath::{EuclideanSpace, Point3};
+use wgpu::{ColorTargetState, Device, Queue};
+
+use crate::app::scene::Scene;
+
+use super::{
+    apply_camera, camera::Camera, editor_camera::EditorCamera, encode_scene_pass,
+    framebuffer::Framebuffer, pipeline::Pipeline, MAIN_SHADER,
+};
+
+/// Renders scenes into a `Framebuffer` without needing a window or surface.
+pub struct HeadlessRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    render_pipeline: Pipeline,
+    camera: Camera,
+    /// View used for scenes without an active camera
+    fallback_camera: EditorCamera,
+    framebuffer: Framebuffer,
+}
+
+impl HeadlessRenderer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub async fn new(width: u32, height: u32) -> anyhow::Result<Self> {
+        Self::create(width, height, false).await
+    }
+
+    /// Prefers the software fallback adapter, so frames do not depend on the installed gpu.
+    pub async fn with_fallback_adapter(width: u32, height: u32) -> anyhow::Result<Self> {
+        Self::create(width, height, true).await
+    }
+
+    async fn create(width: u32, height: u32, prefer_fallback: bool) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let mut options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: prefer_fallback,
+        };
+        let adapter = match instance.request_adapter(&options).await {
+            Some(adapter) => adapter,
+            None => {
+                // Retry with the other kind, software adapters are only handed out on request
+                options.force_fallback_adapter = !prefer_fallback;
+                instance
+                    .request_adapter(&options)
+                    .await
+                    .context("No graphics adapter available")?
+            }
+        };
+        log::info!("Headless rendering on {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: adapter.limits(),
+                    label: Some("Headless Device, Queue"),
+                },
+                None,
+            )
+            .await?;
+
+        let camera_bind_group_layout = Camera::create_bind_group_layout(&device);
+        let camera = Camera::new(&device, &camera_bind_group_layout);
+        let fallback_camera = EditorCamera::new(Point3::new(0.0, 5.0, 10.0), Point3::origin());
+
+        let render_pipeline = Pipeline::new(
+            &device,
+            "Headless Renderer",
+            MAIN_SHADER,
+            &[ColorTargetState {
+                format: Self::FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+            &[&camera_bind_group_layout],
+        );
+
+        let framebuffer = Framebuffer::create(&device, width, height, Self::FORMAT, "Headless");
+
+        Ok(Self {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            render_pipeline,
+            camera,
+            fallback_camera,
+            framebuffer,
+        })
+    }
+
+    /// Renders `scene` and reads the frame back to the cpu.
+    pub fn render(&mut self, scene: &Scene) -> anyhow::Result<image::RgbaImage> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Render Encoder"),
+            });
+        let viewport = apply_camera(
+            &mut self.camera,
+            &self.fallback_camera,
+            scene,
+            &self.queue,
+            [self.framebuffer.width(), self.framebuffer.height()],
+        );
+        encode_scene_pass(
+            &mut encoder,
+            &mut self.render_pipeline,
+            &self.framebuffer,
+            &self.camera,
+            viewport,
+            scene,
+            &self.device,
+            &self.queue,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        self.framebuffer.read_diffuse(&self.device, &self.queue)
+    }
+
+    pub fn render_to_file<P: AsRef<Path>>(&mut self, scene: &Scene, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.render(scene)?
+            .save(path)
+            .with_context(|| format!("Failed to save frame to {}", path.display()))
+    }
+
+    pub fn shared_device(&self) -> Arc<Device> {
+        Arc::clone(&self.device)
+    }
+
+    pub fn shared_queue(&self) -> Arc<Queue> {
+        Arc::clone(&self.queue)
+    }
+}
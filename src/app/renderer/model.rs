@@ -1,28 +1,334 @@
-use std::io::{BufReader, Cursor};
+use std::{
+    io::{BufReader, Cursor},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use anyhow::Ok;
+use anyhow::{Context, Ok};
+use cgmath::{EuclideanSpace, InnerSpace, Point3};
 use wgpu::util::DeviceExt;
 
-use crate::app::assets::{AssetDatabase, AssetHandle, AssetLocation};
+use crate::app::{
+    assets::{AssetDatabase, AssetHandle, AssetLocation},
+    scene::{
+        component::{MeshFilter, Transform},
+        SceneObject,
+    },
+};
 
 use super::{mesh::MeshVertex, texture::Texture};
 
 pub struct Model {
     pub meshes: Vec<AssetHandle<Mesh>>,
     pub materials: Vec<AssetHandle<Material>>,
+    pub nodes: Vec<ModelNode>,
 }
 
+/// Node of a model hierarchy, `meshes` index into `Model::meshes`.
+pub struct ModelNode {
+    pub name: String,
+    pub transform: Transform,
+    pub meshes: Vec<usize>,
+    pub children: Vec<ModelNode>,
+}
+
+impl Model {
+    /// Builds a `SceneObject` tree for this model with a `MeshFilter` per mesh.
+    pub fn instantiate<S: Into<String>>(&self, name: S) -> SceneObject {
+        let root = SceneObject::new(name);
+        for node in &self.nodes {
+            root.add_child(self.instantiate_node(node));
+        }
+        root
+    }
+
+    fn instantiate_node(&self, node: &ModelNode) -> SceneObject {
+        let obj = SceneObject::new(node.name.clone());
+        obj.set_transform(node.transform.clone());
+        match node.meshes.as_slice() {
+            [mesh] => {
+                obj.add_component(self.mesh_filter(*mesh));
+            }
+            meshes => {
+                // One MeshFilter per object, so every primitive gets its own child
+                for &mesh in meshes {
+                    let primitive = SceneObject::new(self.meshes[mesh].asset().name.clone());
+                    primitive.add_component(self.mesh_filter(mesh));
+                    obj.add_child(primitive);
+                }
+            }
+        }
+        for child in &node.children {
+            obj.add_child(self.instantiate_node(child));
+        }
+        obj
+    }
+
+    fn mesh_filter(&self, mesh: usize) -> MeshFilter {
+        let mesh = self.meshes[mesh].clone();
+        let material = mesh.asset().material;
+        match self.materials.get(material) {
+            Some(material) => MeshFilter::with_material(mesh, material.clone()),
+            None => MeshFilter::with_mesh(mesh),
+        }
+    }
+}
+
+static NEXT_MATERIAL_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// Metallic-roughness PBR material, every map is multiplied with its factor.
+/// Textures and factors are read once, when the bind group is first created.
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: AssetHandle<Texture>,
+    pub base_color_texture: AssetHandle<Texture>,
+    /// Tangent space normal map
+    pub normal_texture: AssetHandle<Texture>,
+    /// Roughness in green and metalness in blue, like glTF
+    pub metallic_roughness_texture: AssetHandle<Texture>,
+    /// Ambient occlusion in red
+    pub occlusion_texture: AssetHandle<Texture>,
+    pub emissive_texture: AssetHandle<Texture>,
+    pub factors: MaterialFactors,
+    bind_group: Option<(wgpu::BindGroup, wgpu::Buffer)>,
+    version: u64,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+        }
+    }
+}
+
+impl Material {
+    /// Number of maps, each is bound as a texture followed by its sampler.
+    const TEXTURE_COUNT: u32 = 5;
+
+    /// Material with neutral builtin textures for every map and default factors.
+    pub fn new<S: Into<String>>(name: S, asset_database: &AssetDatabase) -> anyhow::Result<Self> {
+        let white = asset_database.resolve(AssetLocation::Builtin {
+            idnetifying_name: Texture::WHITE.to_string(),
+        })?;
+        Ok(Self {
+            name: name.into(),
+            base_color_texture: white.clone(),
+            normal_texture: asset_database.resolve(AssetLocation::Builtin {
+                idnetifying_name: Texture::NORMAL.to_string(),
+            })?,
+            metallic_roughness_texture: white.clone(),
+            occlusion_texture: white.clone(),
+            emissive_texture: white,
+            factors: MaterialFactors::default(),
+            bind_group: None,
+            version: NEXT_MATERIAL_VERSION.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        for i in 0..Self::TEXTURE_COUNT {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + i * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + i * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("Material bind group"),
+        })
+    }
+
+    fn textures(&self) -> [&AssetHandle<Texture>; Self::TEXTURE_COUNT as usize] {
+        [
+            &self.base_color_texture,
+            &self.normal_texture,
+            &self.metallic_roughness_texture,
+            &self.occlusion_texture,
+            &self.emissive_texture,
+        ]
+    }
+
+    /// Differs between every material created, so recorded draws are redone when the material
+    /// behind a handle is replaced.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The bind group created by the first `bind_group` call, if there was one.
+    pub fn cached_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.bind_group.as_ref().map(|(bind_group, _)| bind_group)
+    }
+
+    pub fn bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> &wgpu::BindGroup {
+        if self.bind_group.is_none() {
+            let f = self.factors;
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Material Buffer: {}", self.name)),
+                contents: bytemuck::cast_slice(&[MaterialUniform {
+                    base_color: f.base_color,
+                    emissive: [f.emissive[0], f.emissive[1], f.emissive[2], 0.0],
+                    metallic: f.metallic,
+                    roughness: f.roughness,
+                    normal_scale: f.normal_scale,
+                    occlusion_strength: f.occlusion_strength,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+            let bind_group = {
+                let textures = self.textures().map(|t| t.asset());
+                let mut entries = vec![wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }];
+                for (i, texture) in textures.iter().enumerate() {
+                    entries.push(wgpu::BindGroupEntry {
+                        binding: 1 + i as u32 * 2,
+                        resource: wgpu::BindingResource::TextureView(texture.view()),
+                    });
+                    entries.push(wgpu::BindGroupEntry {
+                        binding: 2 + i as u32 * 2,
+                        resource: wgpu::BindingResource::Sampler(texture.sampler()),
+                    });
+                }
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: bind_group_layout,
+                    entries: &entries,
+                    label: Some(&format!("Material Bind Group: {}", self.name)),
+                })
+            };
+            self.bind_group = Some((bind_group, buffer));
+        }
+        &self.bind_group.as_ref().unwrap().0
+    }
+}
+
+static NEXT_MESH_VERSION: AtomicU64 = AtomicU64::new(0);
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    pub bounds: Bounds,
+    version: u64,
+}
+
+impl Mesh {
+    pub fn new<S: Into<String>>(
+        name: S,
+        vertex_buffer: wgpu::Buffer,
+        index_buffer: wgpu::Buffer,
+        num_elements: u32,
+        material: usize,
+        bounds: Bounds,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            vertex_buffer,
+            index_buffer,
+            num_elements,
+            material,
+            bounds,
+            version: NEXT_MESH_VERSION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Differs between every mesh created, so recorded draws are redone when the mesh behind
+    /// a handle is replaced.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Object space bounding box and sphere of a mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Bounds {
+    pub fn from_points<I: Iterator<Item = [f32; 3]> + Clone>(points: I) -> Bounds {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for [x, y, z] in points.clone() {
+            min = Point3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = Point3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+        }
+        if min.x > max.x {
+            // No points
+            min = Point3::origin();
+            max = Point3::origin();
+        }
+
+        // Centered on the box, tighter than its half diagonal for most meshes
+        let center = min.midpoint(max);
+        let radius = points
+            .map(|p| (Point3::from(p) - center).magnitude())
+            .fold(0.0, f32::max);
+        Bounds {
+            min,
+            max,
+            center,
+            radius,
+        }
+    }
 }
 
 // pub trait DrawModel<'a> {
@@ -97,40 +403,109 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
+            let mat_text = load_string(&p)
+                .await
+                .map_err(|_| tobj::LoadError::OpenFileFailed)?;
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         },
     )
     .await?;
 
+    let obj_materials =
+        obj_materials.with_context(|| format!("Failed to load the materials of {file_name}"))?;
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let tex_name = m.diffuse_texture.unwrap();
+    for m in obj_materials {
+        let mut material = Material::new(m.name.clone(), &asset_databse)?;
+        let param = |key: &str| {
+            m.unknown_param.get(key).map(|v| {
+                v.split_whitespace()
+                    .filter_map(|f| f.parse::<f32>().ok())
+                    .collect::<Vec<_>>()
+            })
+        };
 
-        let diffuse_texture = Texture::load_texture(&tex_name, device, queue).await?;
+        // Mtl colors assume a texture replaces them, so only untextured materials keep Kd
+        match &m.diffuse_texture {
+            Some(path) => {
+                material.base_color_texture =
+                    load_mtl_texture(path, Texture::SRGB_FORMAT, device, queue, &asset_databse)
+                        .await?
+            }
+            None => {
+                if let Some([r, g, b]) = m.diffuse {
+                    material.factors.base_color = [r, g, b, m.dissolve.unwrap_or(1.0)];
+                }
+            }
+        }
+        if let Some(path) = &m.normal_texture {
+            material.normal_texture =
+                load_mtl_texture(path, Texture::LINEAR_FORMAT, device, queue, &asset_databse)
+                    .await?;
+        }
+        // No standard ao map in mtl, the ambient map is the usual stand in
+        if let Some(path) = &m.ambient_texture {
+            material.occlusion_texture =
+                load_mtl_texture(path, Texture::LINEAR_FORMAT, device, queue, &asset_databse)
+                    .await?;
+        }
+
+        // PBR extension: Pm/Pr and their maps, roughness falls back to the Phong exponent
+        let metallic_map = m.unknown_param.get("map_Pm");
+        let roughness_map = m.unknown_param.get("map_Pr");
+        if metallic_map.is_some() || roughness_map.is_some() {
+            let location = AssetLocation::Resource {
+                path: file_name.to_string(),
+                in_file_ident: Some(format!("{}/metallic_roughness", m.name)),
+            };
+            let texture = pack_metallic_roughness(
+                metallic_map,
+                roughness_map,
+                &location.to_ident(),
+                device,
+                queue,
+            )
+            .await?;
+            material.metallic_roughness_texture = asset_databse.load_texture(location, texture);
+        }
+        if let Some(&[metallic, ..]) = param("Pm").as_deref() {
+            material.factors.metallic = metallic;
+        }
+        match param("Pr").as_deref() {
+            Some(&[roughness, ..]) => material.factors.roughness = roughness,
+            _ if roughness_map.is_some() => {}
+            _ => {
+                if let Some(shininess) = m.shininess {
+                    material.factors.roughness = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+                }
+            }
+        }
+
+        let emissive_map = m.unknown_param.get("map_Ke");
+        if let Some(path) = emissive_map {
+            material.emissive_texture =
+                load_mtl_texture(path, Texture::SRGB_FORMAT, device, queue, &asset_databse)
+                    .await?;
+        }
+        match param("Ke").as_deref() {
+            Some(&[r, g, b, ..]) if r + g + b > 0.0 => material.factors.emissive = [r, g, b],
+            _ if emissive_map.is_some() => material.factors.emissive = [1.0; 3],
+            _ => {}
+        }
 
-        let tex = asset_databse.load_texture(
-            AssetLocation::Resource {
-                path: tex_name,
-                in_file_ident: None,
-            },
-            diffuse_texture,
-        );
         materials.push(asset_databse.load_material(
             AssetLocation::Resource {
                 path: file_name.to_string(),
                 in_file_ident: Some(m.name.clone()),
             },
-            Material {
-                diffuse_texture: tex,
-                name: m.name,
-            },
+            material,
         ));
     }
 
+    let names = models.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
     let meshes = models
         .into_iter()
         .map(|m| {
+            // Uvs and normals are optional in obj, missing ones are zeroed like in gltf
             let vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| MeshVertex {
                     position: [
@@ -138,12 +513,16 @@ pub async fn load_model(
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                    tex_coords: m
+                        .mesh
+                        .texcoords
+                        .get(i * 2..i * 2 + 2)
+                        .map_or([0.0; 2], |uv| [uv[0], uv[1]]),
+                    normal: m
+                        .mesh
+                        .normals
+                        .get(i * 3..i * 3 + 3)
+                        .map_or([0.0; 3], |n| [n[0], n[1], n[2]]),
                 })
                 .collect::<Vec<_>>();
 
@@ -163,34 +542,137 @@ pub async fn load_model(
                     path: file_name.to_string(),
                     in_file_ident: Some(m.name),
                 },
-                Mesh {
-                    name: file_name.to_string(),
+                Mesh::new(
+                    file_name,
                     vertex_buffer,
                     index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                    material: m.mesh.material_id.unwrap_or(0),
-                },
+                    m.mesh.indices.len() as u32,
+                    m.mesh.material_id.unwrap_or(0),
+                    Bounds::from_points(vertices.iter().map(|v| v.position)),
+                ),
             )
         })
         .collect::<Vec<_>>();
 
-    Ok(Model { meshes, materials })
+    let nodes = names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| ModelNode {
+            name,
+            transform: Transform::new(),
+            meshes: vec![i],
+            children: vec![],
+        })
+        .collect();
+
+    Ok(Model {
+        meshes,
+        materials,
+        nodes,
+    })
+}
+
+async fn load_mtl_texture(
+    path: &str,
+    format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    asset_databse: &AssetDatabase,
+) -> anyhow::Result<AssetHandle<Texture>> {
+    let location = AssetLocation::resource(path);
+    if let Some(handle) = asset_databse.texture(location.clone()) {
+        return Ok(handle);
+    }
+    let texture = Texture::load_texture_with_format(path, device, queue, format).await?;
+    Ok(asset_databse.load_texture(location, texture))
+}
+
+/// Packs separate greyscale maps the way the shader expects, roughness in green and
+/// metalness in blue. A missing map is white so only its factor counts.
+async fn pack_metallic_roughness(
+    metallic: Option<&String>,
+    roughness: Option<&String>,
+    label: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let mut maps = Vec::new();
+    for path in [metallic, roughness] {
+        maps.push(match path {
+            Some(path) => Some(image::load_from_memory(&load_binary(path).await?)?.to_luma8()),
+            None => None,
+        });
+    }
+    let (width, height) = maps
+        .iter()
+        .flatten()
+        .map(|m| m.dimensions())
+        .next()
+        .unwrap_or((1, 1));
+    let maps = maps
+        .into_iter()
+        .map(|m| {
+            m.map(|m| {
+                if m.dimensions() == (width, height) {
+                    m
+                } else {
+                    image::imageops::resize(&m, width, height, image::imageops::FilterType::Triangle)
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let sample = |map: &Option<image::GrayImage>, x, y| {
+        map.as_ref().map_or(255, |m| m.get_pixel(x, y).0[0])
+    };
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba([255, sample(&maps[1], x, y), sample(&maps[0], x, y), 255])
+    });
+    Texture::from_image_with_format(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(packed),
+        Some(label),
+        Texture::LINEAR_FORMAT,
+    )
+}
+
+pub fn resource_path(file_name: &str) -> PathBuf {
+    Path::new(env!("OUT_DIR")).join("res").join(file_name)
 }
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let txt = std::fs::read_to_string(path)?;
+    let txt = std::fs::read_to_string(resource_path(file_name))?;
 
     Ok(txt)
 }
 
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let data = std::fs::read(path)?;
+    let data = std::fs::read(resource_path(file_name))?;
 
     Ok(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_of_no_points_is_the_origin() {
+        let bounds = Bounds::from_points(std::iter::empty());
+        assert_eq!(bounds.min, Point3::origin());
+        assert_eq!(bounds.max, Point3::origin());
+        assert_eq!(bounds.center, Point3::origin());
+        assert_eq!(bounds.radius, 0.0);
+    }
+
+    #[test]
+    fn bounds_of_one_point_is_that_point() {
+        let bounds = Bounds::from_points(std::iter::once([1.0, -2.0, 3.0]));
+        let point = Point3::new(1.0, -2.0, 3.0);
+        assert_eq!(bounds.min, point);
+        assert_eq!(bounds.max, point);
+        assert_eq!(bounds.center, point);
+        assert_eq!(bounds.radius, 0.0);
+    }
+}
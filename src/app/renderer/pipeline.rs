@@ -1,12 +1,27 @@
-use wgpu::{BindGroupLayout, ColorTargetState, Device, Queue, RenderBundle, TextureFormat};
+use std::{collections::HashMap, hash::Hash, ops::Range, rc::Rc};
 
-use crate::app::scene::{
-    component::{Component, MeshFilter, TransformRaw, TransformStack},
-    Scene, SceneObject,
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix};
+use wgpu::{
+    util::DeviceExt, BindGroupLayout, ColorTargetState, Device, Queue, RenderBundle,
+    TextureFormat,
+};
+
+use crate::app::{
+    assets::{uuid::Uuid, AssetHandle, AssetLocation},
+    scene::{
+        component::{
+            DirectionalLight, LightRaw, MeshFilter, PointLight, SpotLight, TransformRaw, MAX_LIGHTS,
+        },
+        Scene, SceneObject,
+    },
 };
 
 use super::{
+    camera::Camera,
+    culling::{Frustum, RenderStats},
     mesh::{MeshVertex, Vertex},
+    model::{Material, Mesh},
+    shadow::{ShadowMaps, ShadowView},
     texture::Texture,
 };
 
@@ -14,10 +29,118 @@ pub struct Pipeline {
     pub(crate) pipeline: wgpu::RenderPipeline,
     pub(crate) name: String,
     pub(crate) color_formats: Vec<Option<TextureFormat>>,
-    pub(crate) texture_bind_group_layout: wgpu::BindGroupLayout,
+    pub(crate) material_bind_group_layout: wgpu::BindGroupLayout,
+    lights_buffer: wgpu::Buffer,
+    lights_bind_group_layout: wgpu::BindGroupLayout,
+    lights_bind_group: wgpu::BindGroup,
+    shadows: ShadowMaps,
+    /// Bumped whenever something recorded bundles reference is recreated
+    generation: u64,
+
+    instance_buffer: Option<wgpu::Buffer>,
+    instance_capacity: usize,
+    uploaded_instances: Vec<InstanceRaw>,
+    /// Objects of the uploaded instances, the picking id of an instance is its index + 1
+    instance_objects: Vec<Uuid>,
+    bundle: Option<CachedBundle>,
+    stats: RenderStats,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsUniform {
+    lights: [LightRaw; MAX_LIGHTS],
+    ambient: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+impl LightsUniform {
+    const AMBIENT: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+}
+
+/// Per instance data of the scene pipeline. The picking id travels with the instance because
+/// `instance_index` does not include the first instance of a draw on every backend.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    /// Inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scale
+    normal: [[f32; 3]; 3],
+    /// 0 is left for the background
+    object_id: u32,
+}
+
+impl InstanceRaw {
+    fn new(model: [[f32; 4]; 4], object_id: u32) -> Self {
+        let m = Matrix4::from(model);
+        let linear = Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate());
+        // A singular matrix flattens the mesh, its normals are as good as any then
+        let normal = linear
+            .invert()
+            .map_or(linear, |inverse| inverse.transpose());
+        Self {
+            model,
+            normal: normal.into(),
+            object_id,
+        }
+    }
+}
+
+impl Vertex for InstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
 }
 
 impl Pipeline {
+    /// A second color target in `Framebuffer::ID_FORMAT` receives the id of the drawn object.
     pub fn new(
         device: &Device,
         name: &str,
@@ -30,31 +153,47 @@ impl Pipeline {
             source: wgpu::ShaderSource::Wgsl(shader_source.into()),
         });
 
-        let texture_bind_group_layout =
+        let material_bind_group_layout = Material::create_bind_group_layout(device);
+
+        let mut lights_entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        lights_entries.extend(ShadowMaps::layout_entries());
+        let lights_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("Texture bind group"),
+                entries: &lights_entries,
+                label: Some("Lights bind group"),
             });
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Lights Buffer: {name}")),
+            contents: bytemuck::cast_slice(&[LightsUniform {
+                lights: [bytemuck::Zeroable::zeroed(); MAX_LIGHTS],
+                ambient: LightsUniform::AMBIENT,
+                count: 0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let shadows = ShadowMaps::new(device);
+        let lights_bind_group = Self::create_lights_bind_group(
+            device,
+            name,
+            &lights_bind_group_layout,
+            &lights_buffer,
+            &shadows,
+        );
 
+        // Material is always group 0 and the lights come after the passed in groups
         let mut bind_group_layouts = bind_group_layouts.to_vec();
-        bind_group_layouts.insert(0, &texture_bind_group_layout);
+        bind_group_layouts.insert(0, &material_bind_group_layout);
+        bind_group_layouts.push(&lights_bind_group_layout);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -69,11 +208,15 @@ impl Pipeline {
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
-                buffers: &[MeshVertex::desc(), TransformRaw::desc()],
+                buffers: &[MeshVertex::desc(), InstanceRaw::desc()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
+                entry_point: if color_targets.len() > 1 {
+                    "fs_picking"
+                } else {
+                    "fs_main"
+                },
                 targets: &color_targets
                     .iter()
                     .map(|c| Some(c.clone()))
@@ -107,63 +250,352 @@ impl Pipeline {
             pipeline: render_pipeline,
             name: name.to_string(),
             color_formats: color_targets.iter().map(|c| Some(c.format)).collect(),
-            texture_bind_group_layout,
+            material_bind_group_layout,
+            lights_buffer,
+            lights_bind_group_layout,
+            lights_bind_group,
+            shadows,
+            generation: 0,
+            instance_buffer: None,
+            instance_capacity: 0,
+            uploaded_instances: vec![],
+            instance_objects: vec![],
+            bundle: None,
+            stats: RenderStats::default(),
         }
     }
 
+    fn create_lights_bind_group(
+        device: &Device,
+        name: &str,
+        layout: &BindGroupLayout,
+        lights_buffer: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: lights_buffer.as_entire_binding(),
+        }];
+        entries.extend(shadows.bind_group_entries());
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(&format!("Lights Bind Group: {name}")),
+        })
+    }
+
+    /// `bind_groups` end up in bundles cached between frames, so they have to stay the same.
     pub(super) fn render_scene(
         &mut self,
         scene: &Scene,
+        camera: &Camera,
         device: &Device,
         queue: &Queue,
         bind_groups: &[&wgpu::BindGroup],
-    ) -> Vec<RenderBundle> {
-        let mut transform_stack = TransformStack::new();
-        self.render_scene_obj(
-            scene.root(),
-            device,
-            queue,
-            bind_groups,
-            &mut transform_stack,
-        )
+    ) -> Vec<Rc<RenderBundle>> {
+        let mut collected = Collected::default();
+        Self::collect_scene(scene.root(), &mut collected);
+        let Collected {
+            mut lights,
+            casters,
+            draws,
+        } = collected;
+
+        if lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "Scene has {} lights, only the first {MAX_LIGHTS} are used",
+                lights.len()
+            );
+        }
+        let count = lights.len().min(MAX_LIGHTS);
+
+        if self.shadows.prepare(device, queue, camera, &mut lights[..count]) {
+            self.lights_bind_group = Self::create_lights_bind_group(
+                device,
+                &self.name,
+                &self.lights_bind_group_layout,
+                &self.lights_buffer,
+                &self.shadows,
+            );
+            self.generation += 1;
+        }
+        self.shadows.record(device, queue, &casters);
+
+        let mut uniform = LightsUniform {
+            lights: [bytemuck::Zeroable::zeroed(); MAX_LIGHTS],
+            ambient: LightsUniform::AMBIENT,
+            count: count as u32,
+            _padding: [0; 3],
+        };
+        for (slot, (light, _)) in uniform.lights.iter_mut().zip(&lights) {
+            *slot = *light;
+        }
+        queue.write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        // Shadow casters are not culled, they can be outside the view and still cast into it
+        let frustum = Frustum::from_matrix(&camera.view_projection());
+        let objects = draws.len();
+        let draws = draws
+            .into_iter()
+            .filter(|(mesh, _, instance, _)| {
+                frustum.intersects(&mesh.asset().bounds, &Matrix4::from(instance.model))
+            })
+            .collect::<Vec<_>>();
+
+        let (groups, instances, instance_objects) = Self::group_draws(draws);
+        self.stats = RenderStats {
+            objects,
+            culled: objects - instances.len(),
+            draw_calls: groups.len(),
+        };
+        self.upload_instances(device, queue, &instances);
+        self.instance_objects = instance_objects;
+        self.record_bundle(device, bind_groups, &groups)
+            .into_iter()
+            .collect()
+    }
+
+    /// Statistics of the last `render_scene`.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Object drawn with picking `id` by the last `render_scene`.
+    pub fn picked_object(&self, id: u32) -> Option<Uuid> {
+        let index = id.checked_sub(1)? as usize;
+        self.instance_objects.get(index).cloned()
+    }
+
+    /// Depth passes of the shadow maps prepared by the last `render_scene`.
+    pub(super) fn encode_shadow_passes(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.shadows.encode(encoder);
+    }
+
+    fn collect_scene(obj: SceneObject, out: &mut Collected) {
+        let world = obj.world_matrix();
+
+        if let Some(light) = obj.with_component(|light: &DirectionalLight| {
+            (light.to_raw(&world), light.shadow_view(&world))
+        }) {
+            out.lights.push(light);
+        }
+        if let Some(light) = obj.with_component(|light: &PointLight| light.to_raw(&world)) {
+            out.lights.push((light, None));
+        }
+        if let Some(light) =
+            obj.with_component(|light: &SpotLight| (light.to_raw(&world), light.shadow_view(&world)))
+        {
+            out.lights.push(light);
+        }
+        obj.with_component(|filter: &MeshFilter| {
+            let instance = TransformRaw {
+                model: world.into(),
+            };
+            if let (Some(mesh), Some(material)) = (filter.mesh(), filter.material()) {
+                out.draws
+                    .push((mesh.clone(), material.clone(), instance, obj.id()));
+            }
+            if let Some(mesh) = filter.shadow_caster() {
+                out.casters.push((mesh, instance));
+            }
+        });
+
+        for child in obj.children() {
+            Self::collect_scene(child, out);
+        }
     }
 
-    fn render_scene_obj(
-        &self,
-        obj: SceneObject,
+    /// Groups draws sharing mesh and material, in order of their first appearance.
+    /// Returns the groups and the instance data and objects they index into.
+    fn group_draws(draws: Vec<Draw>) -> (Vec<DrawGroup>, Vec<InstanceRaw>, Vec<Uuid>) {
+        let (groups, members) =
+            group_instances(draws.into_iter().map(|(mesh, material, instance, object)| {
+                let key = (mesh.location.clone(), material.location.clone());
+                (key, (mesh, material), (instance, object))
+            }));
+        let groups = groups
+            .into_iter()
+            .map(|((mesh, material), instances)| DrawGroup {
+                mesh,
+                material,
+                instances,
+            })
+            .collect();
+        let (transforms, objects): (Vec<TransformRaw>, _) = members.into_iter().unzip();
+        let instances = transforms
+            .into_iter()
+            .enumerate()
+            .map(|(i, transform)| InstanceRaw::new(transform.model, i as u32 + 1))
+            .collect();
+        (groups, instances, objects)
+    }
+
+    /// Uploads the instance data if it differs from the last frame, growing the buffer if needed.
+    fn upload_instances(&mut self, device: &Device, queue: &Queue, instances: &[InstanceRaw]) {
+        if self.instance_buffer.is_none() || instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two().max(64);
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("Instance Buffer: {}", self.name)),
+                size: (self.instance_capacity * std::mem::size_of::<InstanceRaw>()) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.uploaded_instances.clear();
+            // Bundles reference the old buffer
+            self.generation += 1;
+        }
+
+        let changed = bytemuck::cast_slice::<_, u8>(instances)
+            != bytemuck::cast_slice::<_, u8>(&self.uploaded_instances);
+        if changed && !instances.is_empty() {
+            queue.write_buffer(
+                self.instance_buffer.as_ref().unwrap(),
+                0,
+                bytemuck::cast_slice(instances),
+            );
+        }
+        if changed {
+            self.uploaded_instances = instances.to_vec();
+        }
+    }
+
+    /// One bundle with an instanced draw per group, reused while the groups stay the same.
+    fn record_bundle(
+        &mut self,
         device: &Device,
-        queue: &Queue,
         bind_groups: &[&wgpu::BindGroup],
-        transform_stack: &mut TransformStack,
-    ) -> Vec<RenderBundle> {
-        transform_stack.push(obj.get_transform().to_raw());
-
-        let mut bundles = vec![];
-        let mf = obj.get_component(MeshFilter::IDENT);
-        if let Some(mesh_filter) = mf {
-            let mfc: &mut Component = &mut mesh_filter.get().borrow_mut();
-            match mfc {
-                Component::MeshFilter(filter) => {
-                    let b = filter.render(self, device, bind_groups, queue, transform_stack);
-                    if let Some(b) = b {
-                        bundles.push(b);
-                    }
-                }
-            }
+        groups: &[DrawGroup],
+    ) -> Option<Rc<RenderBundle>> {
+        if groups.is_empty() {
+            return None;
+        }
+        for group in groups {
+            group
+                .material
+                .asset_mut()
+                .bind_group(device, &self.material_bind_group_layout);
         }
 
-        for child in obj.children() {
-            bundles.append(&mut self.render_scene_obj(
-                child,
-                device,
-                queue,
-                bind_groups,
-                transform_stack,
-            ));
+        let keys = groups
+            .iter()
+            .map(|g| DrawKey {
+                mesh: g.mesh.location.clone(),
+                mesh_version: g.mesh.asset().version(),
+                material: g.material.location.clone(),
+                material_version: g.material.asset().version(),
+                instances: g.instances.clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Some(cached) = self
+            .bundle
+            .as_ref()
+            .filter(|c| c.generation == self.generation && c.keys == keys)
+        {
+            return Some(Rc::clone(&cached.bundle));
         }
 
-        transform_stack.pop();
+        let mut encoder = device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+            label: Some(&format!("Scene Encoder: {}", self.name)),
+            color_formats: &self.color_formats,
+            depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+                format: Texture::DEPTH_FORMAT,
+                depth_read_only: false,
+                stencil_read_only: false,
+            }),
+            sample_count: 1,
+            multiview: None,
+        });
+        encoder.set_pipeline(&self.pipeline);
+        for (i, group) in bind_groups.iter().enumerate() {
+            // +1 because 0 is the material
+            encoder.set_bind_group(i as u32 + 1, group, &[]);
+        }
+        // Lights come after the passed in groups
+        encoder.set_bind_group(bind_groups.len() as u32 + 1, &self.lights_bind_group, &[]);
+        encoder.set_vertex_buffer(1, self.instance_buffer.as_ref().unwrap().slice(..));
+
+        let meshes = groups.iter().map(|g| g.mesh.asset()).collect::<Vec<_>>();
+        let materials = groups.iter().map(|g| g.material.asset()).collect::<Vec<_>>();
+        for ((group, mesh), material) in groups.iter().zip(&meshes).zip(&materials) {
+            encoder.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            encoder.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            encoder.set_bind_group(
+                0,
+                material
+                    .cached_bind_group()
+                    .expect("Bind group was created above"),
+                &[],
+            );
+            encoder.draw_indexed(0..mesh.num_elements, 0, group.instances.clone());
+        }
+
+        let bundle = Rc::new(encoder.finish(&wgpu::RenderBundleDescriptor {
+            label: Some(&format!("Scene Bundle: {}", self.name)),
+        }));
+        self.bundle = Some(CachedBundle {
+            keys,
+            generation: self.generation,
+            bundle: Rc::clone(&bundle),
+        });
+        Some(bundle)
+    }
+}
 
-        bundles
+/// Groups `items` sharing a key, in order of their first appearance. Returns every group with
+/// the range its members take in the returned members.
+pub(super) fn group_instances<K: Hash + Eq, G, T>(
+    items: impl IntoIterator<Item = (K, G, T)>,
+) -> (Vec<(G, Range<u32>)>, Vec<T>) {
+    let mut lookup = HashMap::new();
+    let mut grouped: Vec<(G, Vec<T>)> = vec![];
+    for (key, group, member) in items {
+        let index = *lookup.entry(key).or_insert_with(|| {
+            grouped.push((group, vec![]));
+            grouped.len() - 1
+        });
+        grouped[index].1.push(member);
     }
+
+    let mut members = vec![];
+    let groups = grouped
+        .into_iter()
+        .map(|(group, group_members)| {
+            let start = members.len() as u32;
+            members.extend(group_members);
+            (group, start..members.len() as u32)
+        })
+        .collect();
+    (groups, members)
+}
+
+type Draw = (AssetHandle<Mesh>, AssetHandle<Material>, TransformRaw, Uuid);
+
+/// Everything `render_scene` needs from the scene hierarchy, with evaluated world transforms.
+#[derive(Default)]
+struct Collected {
+    lights: Vec<(LightRaw, Option<ShadowView>)>,
+    casters: Vec<(AssetHandle<Mesh>, TransformRaw)>,
+    draws: Vec<Draw>,
+}
+
+struct DrawGroup {
+    mesh: AssetHandle<Mesh>,
+    material: AssetHandle<Material>,
+    instances: Range<u32>,
+}
+
+/// What the recorded draw of a group depends on.
+#[derive(PartialEq)]
+struct DrawKey {
+    mesh: AssetLocation,
+    mesh_version: u64,
+    material: AssetLocation,
+    material_version: u64,
+    instances: Range<u32>,
+}
+
+struct CachedBundle {
+    keys: Vec<DrawKey>,
+    generation: u64,
+    bundle: Rc<RenderBundle>,
 }
@@ -0,0 +1,623 @@
+use std::{num::NonZeroU64, ops::Range};
+
+use cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform as _, Vector3,
+};
+use wgpu::{Device, Queue, RenderBundle};
+
+use crate::app::{
+    assets::{AssetHandle, AssetLocation},
+    scene::component::{LightRaw, TransformRaw},
+};
+
+use super::{
+    camera::{Camera, OPENGL_TO_WGPU_MATRIX},
+    mesh::{MeshVertex, Vertex},
+    model::Mesh,
+    pipeline::group_instances,
+    texture::Texture,
+};
+
+const SHADOW_SHADER: &str = include_str!("../shaders/shadow.wgsl");
+
+/// Layers of the shadow map array, has to match `shader.wgsl`.
+pub const MAX_SHADOW_LAYERS: usize = 8;
+/// Cascades, and so layers, used by every directional light.
+pub const CASCADES: usize = 4;
+pub const MIN_RESOLUTION: u32 = 256;
+pub const MAX_RESOLUTION: u32 = 2048;
+
+/// How far behind a cascade objects still cast shadows into it.
+const CASTER_DISTANCE: f32 = 50.0;
+/// Blend between logarithmic (1) and uniform (0) cascade splits.
+const SPLIT_LAMBDA: f32 = 0.75;
+const SPOT_NEAR: f32 = 0.05;
+
+/// What a shadow casting light needs to be rendered from.
+pub enum ShadowProjection {
+    Directional {
+        direction: Vector3<f32>,
+        /// Distance from the camera the cascades cover
+        distance: f32,
+    },
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        outer_angle: f32,
+        range: f32,
+    },
+}
+
+pub struct ShadowView {
+    pub projection: ShadowProjection,
+    pub resolution: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowLayerRaw {
+    view_proj: [[f32; 4]; 4],
+    /// x: far split of a cascade, y: uv extent of the rendered region, z: size of a texel in uv
+    params: [f32; 4],
+}
+
+/// Depth map array all shadow casting lights render into, one layer per cascade or spot light.
+/// Lights with a lower resolution only use the top left part of their layer.
+pub struct ShadowMaps {
+    pipeline: wgpu::RenderPipeline,
+    // Only held so the views stay valid
+    _texture: wgpu::Texture,
+    array_view: wgpu::TextureView,
+    layer_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    resolution: u32,
+
+    /// All layers as one uniform array for the lit shader
+    layers_buffer: wgpu::Buffer,
+    /// Every layer on its own aligned offset for the depth passes
+    pass_buffer: wgpu::Buffer,
+    pass_bind_group: wgpu::BindGroup,
+    pass_stride: u64,
+
+    instance_buffer: Option<wgpu::Buffer>,
+    instance_capacity: usize,
+    uploaded_instances: Vec<TransformRaw>,
+    /// Caster groups the bundles draw
+    keys: Vec<CasterKey>,
+    /// Bumped whenever the recorded draws change
+    generation: u64,
+    layer_resolutions: Vec<u32>,
+    layer_matrices: Vec<[[f32; 4]; 4]>,
+    bundles: Vec<CachedLayer>,
+}
+
+/// What the recorded draw of a group of casters depends on.
+#[derive(PartialEq)]
+struct CasterKey {
+    mesh: AssetLocation,
+    mesh_version: u64,
+    instances: Range<u32>,
+}
+
+struct CachedLayer {
+    view_proj: [[f32; 4]; 4],
+    generation: u64,
+    bundle: RenderBundle,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader: Shadow"),
+            source: wgpu::ShaderSource::Wgsl(SHADOW_SHADER.into()),
+        });
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Shadow pass bind group"),
+            });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout: Shadow"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline: Shadow"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[MeshVertex::desc(), TransformRaw::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Open meshes have to cast shadows from both sides
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let layer_size = std::mem::size_of::<ShadowLayerRaw>() as u64;
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let pass_stride = layer_size.div_ceil(alignment) * alignment;
+
+        let layers_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Layers Buffer"),
+            size: layer_size * MAX_SHADOW_LAYERS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pass_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Pass Buffer"),
+            size: pass_stride * MAX_SHADOW_LAYERS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &pass_buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(layer_size),
+                }),
+            }],
+            label: Some("Shadow Pass Bind Group"),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let resolution = MIN_RESOLUTION;
+        let (texture, array_view, layer_views) = Self::create_texture(device, resolution);
+
+        Self {
+            pipeline,
+            _texture: texture,
+            array_view,
+            layer_views,
+            sampler,
+            resolution,
+            layers_buffer,
+            pass_buffer,
+            pass_bind_group,
+            pass_stride,
+            instance_buffer: None,
+            instance_capacity: 0,
+            uploaded_instances: vec![],
+            keys: vec![],
+            generation: 0,
+            layer_resolutions: vec![],
+            layer_matrices: vec![],
+            bundles: vec![],
+        }
+    }
+
+    fn create_texture(
+        device: &Device,
+        resolution: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView, Vec<wgpu::TextureView>) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: MAX_SHADOW_LAYERS as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Maps View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..MAX_SHADOW_LAYERS as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(&format!("Shadow Map Layer {layer}")),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        (texture, array_view, layer_views)
+    }
+
+    /// Entries following the lights uniform in the lit shader's lights bind group.
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: self.layers_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.array_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Assigns layers to the shadow casting lights and uploads their matrices.
+    /// Returns true if the map array was recreated, invalidating bind groups using it.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        camera: &Camera,
+        lights: &mut [(LightRaw, Option<ShadowView>)],
+    ) -> bool {
+        let resolution = lights
+            .iter()
+            .filter_map(|(_, view)| view.as_ref())
+            .map(|view| view.resolution.clamp(MIN_RESOLUTION, MAX_RESOLUTION))
+            .max()
+            .unwrap_or(MIN_RESOLUTION);
+        let recreated = resolution != self.resolution;
+        if recreated {
+            (self._texture, self.array_view, self.layer_views) =
+                Self::create_texture(device, resolution);
+            self.resolution = resolution;
+        }
+
+        let mut layers = vec![];
+        self.layer_resolutions.clear();
+        self.layer_matrices.clear();
+        for (raw, view) in lights.iter_mut() {
+            let Some(view) = view else { continue };
+            let needed = match view.projection {
+                ShadowProjection::Directional { .. } => CASCADES,
+                ShadowProjection::Spot { .. } => 1,
+            };
+            if layers.len() + needed > MAX_SHADOW_LAYERS {
+                log::warn!("Out of shadow map layers, a light is rendered without shadows");
+                continue;
+            }
+            raw.set_shadow_layers(layers.len(), needed);
+
+            let light_resolution = view.resolution.clamp(MIN_RESOLUTION, MAX_RESOLUTION);
+            let params = [
+                0.0,
+                light_resolution as f32 / self.resolution as f32,
+                1.0 / self.resolution as f32,
+                0.0,
+            ];
+            match view.projection {
+                ShadowProjection::Directional {
+                    direction,
+                    distance,
+                } => {
+                    let mut near = camera.znear();
+                    for far in cascade_splits(near, distance) {
+                        let view_proj =
+                            cascade_matrix(camera, direction, near, far, light_resolution);
+                        layers.push(ShadowLayerRaw {
+                            view_proj: view_proj.into(),
+                            params: [far, params[1], params[2], 0.0],
+                        });
+                        self.layer_resolutions.push(light_resolution);
+                        self.layer_matrices.push(view_proj.into());
+                        near = far;
+                    }
+                }
+                ShadowProjection::Spot {
+                    position,
+                    direction,
+                    outer_angle,
+                    range,
+                } => {
+                    let view = Matrix4::look_to_rh(position, direction, up_for(direction));
+                    let proj = cgmath::perspective(
+                        Deg(outer_angle * 2.0),
+                        1.0,
+                        SPOT_NEAR,
+                        range.max(SPOT_NEAR * 2.0),
+                    );
+                    let view_proj = (OPENGL_TO_WGPU_MATRIX * proj * view).into();
+                    layers.push(ShadowLayerRaw { view_proj, params });
+                    self.layer_resolutions.push(light_resolution);
+                    self.layer_matrices.push(view_proj);
+                }
+            }
+        }
+
+        if !layers.is_empty() {
+            queue.write_buffer(&self.layers_buffer, 0, bytemuck::cast_slice(&layers));
+            for (i, layer) in layers.iter().enumerate() {
+                queue.write_buffer(
+                    &self.pass_buffer,
+                    i as u64 * self.pass_stride,
+                    bytemuck::cast_slice(&[*layer]),
+                );
+            }
+        }
+
+        recreated
+    }
+
+    /// Records the depth passes of the layers assigned in `prepare`, with an instanced draw per
+    /// caster mesh. Bundles of layers whose matrix and casters did not change are reused.
+    pub fn record(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        casters: &[(AssetHandle<Mesh>, TransformRaw)],
+    ) {
+        if self.layer_resolutions.is_empty() {
+            self.bundles.clear();
+            return;
+        }
+
+        let (groups, instances) = group_instances(
+            casters
+                .iter()
+                .map(|(mesh, instance)| (mesh.location.clone(), mesh, *instance)),
+        );
+        self.upload_instances(device, queue, &instances);
+        let keys = groups
+            .iter()
+            .map(|(mesh, instances)| CasterKey {
+                mesh: mesh.location.clone(),
+                mesh_version: mesh.asset().version(),
+                instances: instances.clone(),
+            })
+            .collect::<Vec<_>>();
+        if keys != self.keys {
+            self.keys = keys;
+            self.generation += 1;
+        }
+
+        self.bundles.truncate(self.layer_matrices.len());
+        for layer in 0..self.layer_matrices.len() {
+            let view_proj = self.layer_matrices[layer];
+            let cached = self
+                .bundles
+                .get(layer)
+                .is_some_and(|c| c.generation == self.generation && c.view_proj == view_proj);
+            if cached {
+                continue;
+            }
+            let cached = CachedLayer {
+                view_proj,
+                generation: self.generation,
+                bundle: self.record_layer(device, layer, &groups),
+            };
+            match self.bundles.get_mut(layer) {
+                Some(slot) => *slot = cached,
+                None => self.bundles.push(cached),
+            }
+        }
+    }
+
+    /// Uploads the caster instances if they differ from the last frame, growing the buffer if
+    /// needed.
+    fn upload_instances(&mut self, device: &Device, queue: &Queue, instances: &[TransformRaw]) {
+        if instances.len() > self.instance_capacity || self.instance_buffer.is_none() {
+            self.instance_capacity = instances.len().next_power_of_two().max(16);
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Shadow Caster Instance Buffer"),
+                size: (self.instance_capacity * std::mem::size_of::<TransformRaw>()) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.uploaded_instances.clear();
+            // Bundles reference the old buffer
+            self.generation += 1;
+        }
+
+        let changed = bytemuck::cast_slice::<_, u8>(instances)
+            != bytemuck::cast_slice::<_, u8>(&self.uploaded_instances);
+        if changed && !instances.is_empty() {
+            queue.write_buffer(
+                self.instance_buffer.as_ref().unwrap(),
+                0,
+                bytemuck::cast_slice(instances),
+            );
+        }
+        if changed {
+            self.uploaded_instances = instances.to_vec();
+        }
+    }
+
+    fn record_layer(
+        &self,
+        device: &Device,
+        layer: usize,
+        groups: &[(&AssetHandle<Mesh>, Range<u32>)],
+    ) -> RenderBundle {
+        let mut encoder =
+            device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+                label: Some(&format!("Shadow Encoder: Layer {layer}")),
+                color_formats: &[],
+                depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_read_only: false,
+                    stencil_read_only: false,
+                }),
+                sample_count: 1,
+                multiview: None,
+            });
+        encoder.set_pipeline(&self.pipeline);
+        encoder.set_bind_group(
+            0,
+            &self.pass_bind_group,
+            &[(layer as u64 * self.pass_stride) as u32],
+        );
+        encoder.set_vertex_buffer(1, self.instance_buffer.as_ref().unwrap().slice(..));
+
+        let meshes = groups.iter().map(|(m, _)| m.asset()).collect::<Vec<_>>();
+        for (mesh, (_, instances)) in meshes.iter().zip(groups) {
+            encoder.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            encoder.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            encoder.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+        encoder.finish(&wgpu::RenderBundleDescriptor {
+            label: Some(&format!("Shadow Bundle: Layer {layer}")),
+        })
+    }
+
+    /// Encodes one depth pass per used layer, has to run before the lit pass.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        for (layer, cached) in self.bundles.iter().enumerate() {
+            let resolution = self.layer_resolutions[layer] as f32;
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Render Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_viewport(0.0, 0.0, resolution, resolution, 0.0, 1.0);
+            pass.execute_bundles(std::iter::once(&cached.bundle));
+        }
+    }
+}
+
+/// Split distances between `near` and `distance`, the last one is `distance`.
+fn cascade_splits(near: f32, distance: f32) -> [f32; CASCADES] {
+    let distance = distance.max(near * 2.0);
+    let mut splits = [0.0; CASCADES];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let p = (i + 1) as f32 / CASCADES as f32;
+        let log = near * (distance / near).powf(p);
+        let uniform = near + (distance - near) * p;
+        *split = SPLIT_LAMBDA * log + (1.0 - SPLIT_LAMBDA) * uniform;
+    }
+    splits
+}
+
+/// Orthographic projection around the bounding sphere of a slice of the camera frustum.
+fn cascade_matrix(
+    camera: &Camera,
+    direction: Vector3<f32>,
+    near: f32,
+    far: f32,
+    resolution: u32,
+) -> Matrix4<f32> {
+    let corners = camera.frustum_corners(near, far);
+    let center = Point3::centroid(&corners);
+    let radius = corners
+        .iter()
+        .map(|c| (c - center).magnitude())
+        .fold(0.0, f32::max);
+    // Rounded so the projection does not change size while the camera rotates
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let direction = direction.normalize();
+    let up = up_for(direction);
+
+    // Snap the center to whole texels so shadow edges do not shimmer when the camera moves
+    let texel = 2.0 * radius / resolution as f32;
+    let rotation = Matrix4::look_to_rh(Point3::origin(), direction, up);
+    let mut snapped = rotation.transform_point(center);
+    snapped.x = (snapped.x / texel).floor() * texel;
+    snapped.y = (snapped.y / texel).floor() * texel;
+    let center = rotation
+        .invert()
+        .map_or(center, |inverse| inverse.transform_point(snapped));
+
+    let eye = center - direction * (radius + CASTER_DISTANCE);
+    let view = Matrix4::look_to_rh(eye, direction, up);
+    let proj = cgmath::ortho(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_DISTANCE,
+    );
+    OPENGL_TO_WGPU_MATRIX * proj * view
+}
+
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.normalize().y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
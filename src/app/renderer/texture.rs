@@ -10,11 +10,37 @@ pub struct Texture {
     texture: Arc<wgpu::Texture>,
     view: wgpu::TextureView,
     sampler: wgpu::Sampler,
-    bind_group: Option<wgpu::BindGroup>,
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const SRGB_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// Format for textures holding data instead of colors, like normal or roughness maps.
+    pub const LINEAR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    pub const WHITE: &'static str = "white";
+    /// Flat tangent space normal.
+    pub const NORMAL: &'static str = "normal";
+
+    /// Creates the 1x1 texture registered as `builtin:<name>`.
+    pub fn builtin(name: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        let (color, format) = match name {
+            Self::WHITE => ([255, 255, 255, 255], Self::SRGB_FORMAT),
+            Self::NORMAL => ([128, 128, 255, 255], Self::LINEAR_FORMAT),
+            _ => bail!("Unknown builtin texture {name}"),
+        };
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba(color),
+        ));
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(&format!("builtin:{name}")),
+            format,
+        )
+    }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
@@ -56,7 +82,6 @@ impl Texture {
             texture: Arc::new(texture),
             view,
             sampler,
-            bind_group: None,
             name: label.to_string(),
         }
     }
@@ -78,7 +103,9 @@ impl Texture {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -101,7 +128,6 @@ impl Texture {
             texture: Arc::new(texture),
             view,
             sampler,
-            bind_group: None,
             name: label.to_string(),
         }
     }
@@ -115,9 +141,19 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+    ) -> Result<Self> {
+        Self::from_bytes_with_format(device, queue, bytes, label, Self::SRGB_FORMAT)
+    }
+
+    pub fn from_bytes_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image_with_format(device, queue, &img, Some(label), format)
     }
 
     pub fn from_image(
@@ -125,6 +161,17 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(device, queue, img, label, Self::SRGB_FORMAT)
+    }
+
+    /// `format` has to be one of the 8 bit rgba formats.
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -141,7 +188,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -177,7 +224,6 @@ impl Texture {
             texture: Arc::new(texture),
             view,
             sampler,
-            bind_group: None,
             name: label.unwrap_or("unset").to_string(),
         })
     }
@@ -186,39 +232,39 @@ impl Texture {
         file_name: &str,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+    ) -> anyhow::Result<Texture> {
+        Self::load_texture_with_format(file_name, device, queue, Self::SRGB_FORMAT).await
+    }
+
+    pub async fn load_texture_with_format(
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
     ) -> anyhow::Result<Texture> {
         let data = load_binary(file_name).await?;
-        Texture::from_bytes(device, queue, &data, file_name)
+        Texture::from_bytes_with_format(device, queue, &data, file_name, format)
     }
 
-    pub fn bind_group(
-        &mut self,
+    /// Replaces the sampler, the label of `descriptor` is ignored.
+    pub fn with_sampler(
+        mut self,
         device: &wgpu::Device,
-        bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> &wgpu::BindGroup {
-        if self.bind_group.is_none() {
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&self.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                ],
-                label: Some(&format!("Texture Bind Group: {}", self.name)),
-            });
-            self.bind_group = Some(bind_group);
-        }
-        self.bind_group.as_ref().unwrap()
+        descriptor: &wgpu::SamplerDescriptor,
+    ) -> Self {
+        self.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format!("Sampler: {}", self.name)),
+            ..descriptor.clone()
+        });
+        self
     }
 
     pub fn view(&self) -> &wgpu::TextureView {
         &self.view
     }
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
     pub fn view_new(&self) -> wgpu::TextureView {
         self.texture
             .create_view(&wgpu::TextureViewDescriptor::default())
@@ -1,17 +1,32 @@
+mod behaviour;
 pub mod component;
+mod history;
+pub mod query;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    path::Path,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use cgmath::{Matrix4, SquareMatrix, Vector3};
 
-use imgui::Ui;
+use imgui::{Key, Ui};
 use serde::{Deserialize, Serialize};
 
 use crate::gui::ui;
 
-use self::component::{
-    ComponentContainer, ComponentHandle, ComponentIdentifier, ComponentPacker, Transform,
-};
+pub use self::behaviour::BehaviourContext;
+use self::history::{Command, History};
+
+use self::component::{Camera, Component, ComponentContainer, ComponentHandle, Transform};
 
-use super::assets::uuid::{Uuid, *};
+use super::assets::{
+    uuid::{Uuid, *},
+    AssetDatabase,
+};
 
 pub struct Scene {
     inter: Rc<RefCell<InterScene>>,
@@ -26,8 +41,47 @@ struct InterScene {
     //Payload for internal function
     #[serde(skip)]
     selected: Option<Uuid>,
+    #[serde(skip)]
+    history: History,
+    #[serde(skip)]
+    dragged: Option<Uuid>,
+    #[serde(skip, default = "InterScene::default_keep_world")]
+    keep_world_transform: bool,
+    /// Whether this is a copy the behaviours run on, see `Scene::play`
+    #[serde(skip)]
+    playing: bool,
+    /// Time not yet covered by fixed updates
+    #[serde(skip)]
+    fixed_time: Duration,
+}
+
+impl InterScene {
+    fn default_keep_world() -> bool {
+        true
+    }
+}
+
+/// Hierarchy edit requested by the gui, applied once the tree is drawn.
+enum HierarchyAction {
+    CreateChild(SceneObject),
+    Duplicate(SceneObject),
+    Delete(SceneObject),
+    Drop {
+        object: Uuid,
+        target: SceneObject,
+        position: DropPosition,
+    },
+}
+
+/// Where a dragged object lands relative to the node it was dropped on.
+enum DropPosition {
+    Before,
+    Into,
+    After,
 }
 
+const HIERARCHY_PAYLOAD: &str = "SCENE_OBJECT";
+
 impl Serialize for Scene {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -70,6 +124,11 @@ impl Scene {
                 uuid: id,
                 // payload
                 selected: None,
+                history: History::default(),
+                dragged: None,
+                keep_world_transform: InterScene::default_keep_world(),
+                playing: false,
+                fixed_time: Duration::ZERO,
             })),
         }
     }
@@ -82,20 +141,180 @@ impl Scene {
         self.inter.borrow().name.clone()
     }
     pub fn find(&self, id: &Uuid) -> Option<SceneObject> {
-        fn find(obj: &SceneObject, id: &Uuid) -> Option<SceneObject> {
-            if &obj.id() == id {
-                return Some(obj.clone());
-            } else {
-                obj.children().iter().find_map(|f| find(f, id))
-            }
+        self.objects().find(|obj| &obj.id() == id)
+    }
+
+    /// First active camera in hierarchy order, with its world matrix.
+    pub fn active_camera(&self) -> Option<(Camera, Matrix4<f32>)> {
+        self.query::<Camera>().find_map(|(obj, camera)| {
+            let camera = camera.get::<Camera>()?.clone();
+            camera.active.then(|| (camera, obj.world_matrix()))
+        })
+    }
+
+    /// Selects the object with `id` in the editor, `None` clears the selection.
+    pub fn select(&self, id: Option<Uuid>) {
+        self.inter.borrow_mut().selected = id;
+    }
+
+    pub fn selected_object(&self) -> Option<SceneObject> {
+        let selected = self.inter.borrow().selected.clone();
+        selected.and_then(|id| self.find(&id))
+    }
+
+    /// Adds `child` as the last child of `parent`, undoable.
+    pub fn add_child(&self, parent: &SceneObject, child: SceneObject) {
+        let index = parent.child_count();
+        self.execute(Command::Place {
+            object: child,
+            from: None,
+            to: Some((parent.clone(), index)),
+        });
+    }
+
+    /// Takes `object` and its children out of the scene, undoable. The root stays.
+    pub fn remove_object(&self, object: &SceneObject) {
+        let Some(from) = object.slot() else {
+            return;
+        };
+        self.execute(Command::Place {
+            object: object.clone(),
+            from: Some(from),
+            to: None,
+        });
+        self.validate_selection();
+    }
+
+    /// Copies `object` and its children with new ids right after it, undoable.
+    pub fn duplicate_object(&self, object: &SceneObject) -> Option<SceneObject> {
+        let (parent, index) = object.slot()?;
+        let copy = object.duplicate();
+        self.execute(Command::Place {
+            object: copy.clone(),
+            from: None,
+            to: Some((parent, index + 1)),
+        });
+        Some(copy)
+    }
+
+    /// Moves `object` in front of the child at `index` of `parent` (the end if past it),
+    /// undoable. With `keep_world` its transform is adjusted so it stays in place.
+    pub fn move_object(
+        &self,
+        object: &SceneObject,
+        parent: &SceneObject,
+        index: usize,
+        keep_world: bool,
+    ) {
+        let Some((from_parent, from_index)) = object.slot() else {
+            return;
+        };
+        if object.contains(parent) {
+            return;
+        }
+        let index = index.min(parent.child_count());
+        let same_parent = Rc::ptr_eq(&from_parent.inter, &parent.inter);
+        // Later siblings shift down once the object is taken out
+        let index = if same_parent && from_index < index {
+            index - 1
+        } else {
+            index
+        };
+        if same_parent && from_index == index {
+            return;
+        }
+
+        let place = Command::Place {
+            object: object.clone(),
+            from: Some((from_parent, from_index)),
+            to: Some((parent.clone(), index)),
+        };
+        if !keep_world {
+            self.execute(place);
+            return;
+        }
+        let parent_inverse = parent
+            .world_matrix()
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        let before = object.state();
+        let mut after = object.state();
+        after.components.transform =
+            Transform::from_matrix(&(parent_inverse * object.world_matrix()));
+        self.execute(Command::Batch(vec![
+            place,
+            Command::Modify {
+                object: object.clone(),
+                before,
+                after,
+            },
+        ]));
+    }
+
+    fn execute(&self, command: Command) {
+        self.inter.borrow_mut().history.execute(command);
+    }
+
+    pub fn undo(&self) {
+        if self.inter.borrow_mut().history.undo() {
+            self.validate_selection();
         }
-        find(&self.root(), id)
+    }
+
+    pub fn redo(&self) {
+        if self.inter.borrow_mut().history.redo() {
+            self.validate_selection();
+        }
+    }
+
+    /// Records the edits made to the selected object as one undo step once `editing` (a drag
+    /// or text input going on) ends, a whole drag becomes one step.
+    pub fn record_edits(&self, editing: bool) {
+        // Behaviours move objects every frame, that is not worth undoing
+        if self.is_playing() {
+            return;
+        }
+        let selected = self.selected_object();
+        self.inter
+            .borrow_mut()
+            .history
+            .track(selected.as_ref(), editing);
+    }
+
+    /// Clears the selection if undo or redo took the object out of the scene.
+    fn validate_selection(&self) {
+        if self.selected_object().is_none() {
+            self.select(None);
+        }
+    }
+
+    pub fn to_yaml(&self) -> anyhow::Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    /// Deserializes a scene, resolving its asset references against `asset_db`.
+    pub fn from_yaml(yaml: &str, asset_db: &AssetDatabase) -> anyhow::Result<Scene> {
+        Ok(asset_db.resolve_scope(|| serde_yaml::from_str::<Scene>(yaml))?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_yaml()?)
+            .with_context(|| format!("Failed to write scene to {}", path.display()))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, asset_db: &AssetDatabase) -> anyhow::Result<Scene> {
+        let path = path.as_ref();
+        let yaml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scene from {}", path.display()))?;
+        Scene::from_yaml(&yaml, asset_db)
+            .with_context(|| format!("Failed to parse scene {}", path.display()))
     }
 
     fn reparent(self) -> Self {
         fn reparent_obj(obj: &SceneObject) {
             obj.children().iter().for_each(|c| {
-                c.inter.borrow_mut().parent = Some(obj.clone());
+                c.inter.borrow_mut().parent = Some(obj.downgrade());
                 reparent_obj(c);
             })
         }
@@ -111,12 +330,26 @@ impl Scene {
             let hierachy = ui.window("Hierachy").begin();
             if hierachy.is_some() {
                 ui.text(&format!("Name: {}", self.name()));
-                ui.separator();
-                Self::scene_object_hierachy(
-                    ui,
-                    &self.root(),
-                    &mut self.inter.borrow_mut().selected,
+                ui.checkbox(
+                    "Keep world transform",
+                    &mut self.inter.borrow_mut().keep_world_transform,
                 );
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Objects keep their place in the world when reparented");
+                }
+                ui.separator();
+                let mut action = None;
+                {
+                    let root = self.root();
+                    let mut inter = self.inter.borrow_mut();
+                    let InterScene {
+                        selected, dragged, ..
+                    } = &mut *inter;
+                    Self::scene_object_hierachy(ui, &root, true, selected, dragged, &mut action);
+                }
+                if let Some(action) = action {
+                    self.apply_hierarchy_action(action);
+                }
             }
         }
         {
@@ -128,40 +361,174 @@ impl Scene {
                 }
             }
         }
+        self.history_gui(ui);
     }
-    fn scene_object_hierachy(ui: &Ui, scene_object: &SceneObject, selected: &mut Option<Uuid>) {
+
+    fn history_gui(&self, ui: &Ui) {
+        // Text inputs have their own undo
+        if ui.io().key_ctrl && !ui.io().want_text_input {
+            if ui.is_key_pressed(Key::Z) && !ui.io().key_shift {
+                self.undo();
+            } else if ui.is_key_pressed(Key::Y) || ui.is_key_pressed(Key::Z) {
+                self.redo();
+            }
+        }
+
+        let history = ui.window("History").begin();
+        if history.is_none() {
+            return;
+        }
+        let (done, undone) = self.inter.borrow().history.labels();
+        if ui.button("Undo") {
+            self.undo();
+        }
+        ui.same_line();
+        if ui.button("Redo") {
+            self.redo();
+        }
+        ui.separator();
+        // Clicking an entry undoes or redoes up to and including it
+        for (i, label) in done.iter().enumerate() {
+            let current = i + 1 == done.len();
+            if ui
+                .selectable_config(format!("{label}##history_done_{i}"))
+                .selected(current)
+                .build()
+            {
+                for _ in i + 1..done.len() {
+                    self.undo();
+                }
+            }
+        }
+        for (i, label) in undone.iter().enumerate() {
+            let _color = ui.push_style_color(
+                imgui::StyleColor::Text,
+                ui.style_color(imgui::StyleColor::TextDisabled),
+            );
+            if ui.selectable(format!("{label}##history_undone_{i}")) {
+                for _ in 0..=i {
+                    self.redo();
+                }
+            }
+        }
+    }
+    fn scene_object_hierachy(
+        ui: &Ui,
+        scene_object: &SceneObject,
+        is_root: bool,
+        selected: &mut Option<Uuid>,
+        dragged: &mut Option<Uuid>,
+        action: &mut Option<HierarchyAction>,
+    ) {
+        let id = scene_object.id();
         let is_leaf = scene_object.child_count() == 0;
         let node_open = ui
-            .tree_node_config::<String, String>(scene_object.id())
+            .tree_node_config::<String, String>(id.clone())
             .default_open(true)
             .label::<String, String>(scene_object.name())
-            .selected(selected.clone().map_or(false, |id| id == scene_object.id()))
+            .selected(selected.as_ref() == Some(&id))
             .open_on_double_click(true)
             .leaf(is_leaf)
             .push();
-        if let Some(_) = node_open {
-            if ui.is_item_clicked() {
-                *selected = Some(scene_object.id());
+        if ui.is_item_clicked() {
+            *selected = Some(id.clone());
+        }
+        let popup_id = format!("hierachy_context_popup_{id}");
+        if ui.is_item_clicked_with_button(imgui::MouseButton::Right) {
+            ui.open_popup(&popup_id);
+        }
+
+        if !is_root {
+            if let Some(_tooltip) = ui.drag_drop_source_config(HIERARCHY_PAYLOAD).begin() {
+                ui.text(scene_object.name());
+                *dragged = Some(id.clone());
             }
-            if ui.is_item_clicked_with_button(imgui::MouseButton::Right) {
-                ui.open_popup("hierachy_scene_context_popup");
+        }
+        if let Some(target) = ui.drag_drop_target() {
+            let accepted = target
+                .accept_payload_empty(HIERARCHY_PAYLOAD, imgui::DragDropFlags::empty())
+                .is_some();
+            if let Some(object) = dragged.take_if(|_| accepted) {
+                // Top and bottom quarters of the node reorder, the middle reparents
+                let [_, top] = ui.item_rect_min();
+                let [_, bottom] = ui.item_rect_max();
+                let edge = (bottom - top) * 0.25;
+                let y = ui.io().mouse_pos[1];
+                let position = if is_root {
+                    DropPosition::Into
+                } else if y < top + edge {
+                    DropPosition::Before
+                } else if y > bottom - edge && (is_leaf || node_open.is_none()) {
+                    DropPosition::After
+                } else {
+                    DropPosition::Into
+                };
+                *action = Some(HierarchyAction::Drop {
+                    object,
+                    target: scene_object.clone(),
+                    position,
+                });
             }
-            {
-                let popup = ui.begin_popup("hierachy_scene_context_popup");
-                if let Some(_) = popup {
-                    ui.menu_item("Test");
-                    ui.checkbox("Opened", &mut false);
+            target.pop();
+        }
+
+        if let Some(_popup) = ui.begin_popup(&popup_id) {
+            if ui.menu_item("Create Empty") {
+                *action = Some(HierarchyAction::CreateChild(scene_object.clone()));
+            }
+            if !is_root {
+                if ui.menu_item("Duplicate") {
+                    *action = Some(HierarchyAction::Duplicate(scene_object.clone()));
+                }
+                if ui.menu_item("Delete") {
+                    *action = Some(HierarchyAction::Delete(scene_object.clone()));
                 }
             }
+        }
 
+        if node_open.is_some() {
             for child in scene_object.children() {
-                Scene::scene_object_hierachy(ui, &child, selected);
+                Scene::scene_object_hierachy(ui, &child, false, selected, dragged, action);
+            }
+        }
+    }
+
+    fn apply_hierarchy_action(&self, action: HierarchyAction) {
+        match action {
+            HierarchyAction::CreateChild(parent) => {
+                let child = SceneObject::new("Empty");
+                self.add_child(&parent, child.clone());
+                self.select(Some(child.id()));
+            }
+            HierarchyAction::Duplicate(object) => {
+                if let Some(copy) = self.duplicate_object(&object) {
+                    self.select(Some(copy.id()));
+                }
+            }
+            HierarchyAction::Delete(object) => self.remove_object(&object),
+            HierarchyAction::Drop {
+                object,
+                target,
+                position,
+            } => {
+                let Some(object) = self.find(&object) else {
+                    return;
+                };
+                let slot = match position {
+                    DropPosition::Into => Some((target.clone(), target.child_count())),
+                    DropPosition::Before => target.slot(),
+                    DropPosition::After => target.slot().map(|(parent, i)| (parent, i + 1)),
+                };
+                if let Some((parent, index)) = slot {
+                    let keep_world = self.inter.borrow().keep_world_transform;
+                    self.move_object(&object, &parent, index, keep_world);
+                }
             }
         }
     }
 }
 
-#[derive(PartialEq, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct InterSceneObject {
     name: String,
     uuid: Uuid,
@@ -169,7 +536,13 @@ struct InterSceneObject {
 
     childs: Vec<SceneObject>,
     #[serde(skip)]
-    parent: Option<SceneObject>,
+    parent: Option<WeakSceneObject>,
+
+    // Cached matrices of the transform, `None` while out of date
+    #[serde(skip)]
+    local: Cell<Option<Matrix4<f32>>>,
+    #[serde(skip)]
+    world: Cell<Option<Matrix4<f32>>>,
 }
 
 // impl Debug for InterSceneObject {
@@ -181,11 +554,50 @@ struct InterSceneObject {
 //     }
 // }
 
-#[derive(PartialEq)]
+/// Name, transform and components of an object, without its children.
+struct ObjectState {
+    name: String,
+    components: ComponentContainer,
+}
+
+impl Clone for ObjectState {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            components: self.components.duplicate(),
+        }
+    }
+}
+
+/// Shared handle to an object, objects are equal only if they are the same object.
 pub struct SceneObject {
     inter: Rc<RefCell<InterSceneObject>>,
 }
 
+impl PartialEq for SceneObject {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inter, &other.inter)
+    }
+}
+
+/// Handle that does not keep the object alive, used for links back up the graph.
+#[derive(Clone)]
+pub struct WeakSceneObject {
+    inter: Weak<RefCell<InterSceneObject>>,
+}
+
+impl WeakSceneObject {
+    pub fn upgrade(&self) -> Option<SceneObject> {
+        self.inter.upgrade().map(|inter| SceneObject { inter })
+    }
+}
+
+impl PartialEq for WeakSceneObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.inter.ptr_eq(&other.inter)
+    }
+}
+
 impl Serialize for SceneObject {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -201,9 +613,11 @@ impl<'de> Deserialize<'de> for SceneObject {
         D: serde::Deserializer<'de>,
     {
         let inter = InterSceneObject::deserialize(deserializer)?;
-        Ok(SceneObject {
+        let s = SceneObject {
             inter: Rc::new(RefCell::new(inter)),
-        })
+        };
+        s.inter.borrow_mut().components.attach(&s);
+        Ok(s)
     }
 }
 
@@ -224,21 +638,25 @@ impl SceneObject {
                 childs: vec![],
                 parent: None,
                 components: ComponentContainer::empty(),
+                local: Cell::new(None),
+                world: Cell::new(None),
             })),
         };
-        s.inter.borrow_mut().components.attach(s.clone());
+        s.inter.borrow_mut().components.attach(&s);
         s
     }
 
-    pub fn parent(&self) -> Option<SceneObject> {
-        if self.has_parent() {
-            self.inter.borrow().parent.clone()
-        } else {
-            None
+    pub fn downgrade(&self) -> WeakSceneObject {
+        WeakSceneObject {
+            inter: Rc::downgrade(&self.inter),
         }
     }
+
+    pub fn parent(&self) -> Option<SceneObject> {
+        self.inter.borrow().parent.as_ref()?.upgrade()
+    }
     pub fn has_parent(&self) -> bool {
-        self.inter.borrow().parent.is_some()
+        self.parent().is_some()
     }
     pub fn id(&self) -> Uuid {
         self.inter.borrow().uuid.clone()
@@ -246,14 +664,11 @@ impl SceneObject {
     pub fn name(&self) -> String {
         self.inter.borrow().name.clone()
     }
+    /// Detaches `child` if it is a child of this object.
+    #[allow(dead_code)]
     pub fn remove_child(&self, child: &SceneObject) {
-        let childs = &mut self.inter.borrow_mut().childs;
-        if childs.contains(child) {
-            let index = childs
-                .iter()
-                .position(|c| child == c)
-                .expect("Tested if in vec");
-            childs.remove(index);
+        if child.parent().as_ref() == Some(self) {
+            child.detach();
         }
     }
     pub fn children(&self) -> Vec<SceneObject> {
@@ -263,30 +678,223 @@ impl SceneObject {
         self.inter.borrow().childs.len()
     }
 
+    /// Makes `child` the last child of this object, see `set_parent`.
+    ///
+    /// Panics if `child` is this object or one of its ancestors.
     pub fn add_child(&self, child: SceneObject) {
-        if let Some(parent) = child.parent() {
-            parent.remove_child(self);
+        child
+            .set_parent(self)
+            .expect("A child can not be its own ancestor");
+    }
+
+    /// Moves this object with its subtree to the end of the children of `parent`.
+    pub fn set_parent(&self, parent: &SceneObject) -> anyhow::Result<()> {
+        if self.contains(parent) {
+            bail!(
+                "Can not make {} a child of itself or its descendant {}",
+                self.name(),
+                parent.name()
+            );
         }
-        child.inter.borrow_mut().parent = Some(self.clone());
-        self.inter.borrow_mut().childs.push(child);
+        self.detach();
+        parent.insert_child(usize::MAX, self.clone());
+        Ok(())
+    }
+
+    /// Takes this object with its subtree out of its parent. Nothing else references it,
+    /// so it is freed once the last handle is dropped.
+    pub fn detach(&self) {
+        self.detach_from_parent();
+    }
+
+    /// Detaches this object and tears down the components of its whole subtree.
+    /// The objects must not be used afterwards, they are freed with their last handle.
+    pub fn destroy(&self) {
+        self.detach();
+        self.teardown();
+    }
+
+    fn teardown(&self) {
+        for child in self.inter.borrow_mut().childs.drain(..) {
+            child.inter.borrow_mut().parent = None;
+            child.teardown();
+        }
+        self.inter.borrow_mut().components.destroy();
     }
 
     pub fn get_transform(&self) -> Transform {
         self.inter.borrow().components.transform.clone()
     }
+
+    pub fn set_transform(&self, transform: Transform) {
+        self.inter.borrow_mut().components.transform = transform;
+        self.transform_changed();
+    }
+
+    /// Removes this object from the children of its parent, returns the parent and the index.
+    fn detach_from_parent(&self) -> Option<(SceneObject, usize)> {
+        let (parent, index) = self.slot()?;
+        parent.inter.borrow_mut().childs.remove(index);
+        self.inter.borrow_mut().parent = None;
+        self.invalidate_world();
+        Some((parent, index))
+    }
+
+    /// Parent and index among its children, `None` for the root.
+    fn slot(&self) -> Option<(SceneObject, usize)> {
+        let parent = self.parent()?;
+        let index = parent
+            .inter
+            .borrow()
+            .childs
+            .iter()
+            .position(|c| Rc::ptr_eq(&c.inter, &self.inter))?;
+        Some((parent, index))
+    }
+
+    /// Whether `other` is this object or one of its descendants.
+    fn contains(&self, other: &SceneObject) -> bool {
+        let mut current = Some(other.clone());
+        while let Some(object) = current {
+            if Rc::ptr_eq(&object.inter, &self.inter) {
+                return true;
+            }
+            current = object.parent();
+        }
+        false
+    }
+
+    /// Deep copy of this object and its children with new ids, without a parent.
+    fn duplicate(&self) -> SceneObject {
+        let copy = SceneObject::new(self.name());
+        copy.restore(&self.state());
+        for child in self.children() {
+            copy.insert_child(usize::MAX, child.duplicate());
+        }
+        copy
+    }
+
+    /// Inserts an object without a parent at `index`, or at the end if it is past the last child.
+    fn insert_child(&self, index: usize, child: SceneObject) {
+        child.inter.borrow_mut().parent = Some(self.downgrade());
+        child.invalidate_world();
+        let childs = &mut self.inter.borrow_mut().childs;
+        childs.insert(index.min(childs.len()), child);
+    }
+
+    fn state(&self) -> ObjectState {
+        let inter = self.inter.borrow();
+        ObjectState {
+            name: inter.name.clone(),
+            components: inter.components.duplicate(),
+        }
+    }
+
+    /// Serialized state, only comparable with earlier calls on the same object.
+    fn state_yaml(&self) -> String {
+        let inter = self.inter.borrow();
+        serde_yaml::to_string(&(&inter.name, &inter.components)).unwrap_or_default()
+    }
+
+    fn restore(&self, state: &ObjectState) {
+        {
+            let mut inter = self.inter.borrow_mut();
+            inter.name = state.name.clone();
+            inter.components.replace(state.components.duplicate());
+        }
+        self.transform_changed();
+    }
+
+    /// Matrix from the local space of this object to the space of its parent.
+    pub fn local_matrix(&self) -> Matrix4<f32> {
+        let inter = self.inter.borrow();
+        if let Some(local) = inter.local.get() {
+            return local;
+        }
+        let local = inter.components.transform.matrix();
+        inter.local.set(Some(local));
+        local
+    }
+
+    /// Matrix from the local space of this object to world space.
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        if let Some(world) = self.inter.borrow().world.get() {
+            return world;
+        }
+        let local = self.local_matrix();
+        let world = match self.parent() {
+            Some(parent) => parent.world_matrix() * local,
+            None => local,
+        };
+        self.inter.borrow().world.set(Some(world));
+        world
+    }
+
+    pub fn world_position(&self) -> Vector3<f32> {
+        self.world_matrix().w.truncate()
+    }
+
+    /// Moves this object to `position` in world space by changing its local position.
+    pub fn set_world_position(&self, position: Vector3<f32>) {
+        let parent_inverse = self
+            .parent()
+            .and_then(|parent| parent.world_matrix().invert())
+            .unwrap_or_else(Matrix4::identity);
+        let mut transform = self.get_transform();
+        transform.set_position((parent_inverse * position.extend(1.0)).truncate());
+        self.set_transform(transform);
+    }
+
+    fn transform_changed(&self) {
+        self.inter.borrow().local.set(None);
+        self.invalidate_world();
+    }
+
+    /// Drops the cached world matrices of this object and its descendants.
+    fn invalidate_world(&self) {
+        // A cached world matrix implies a cached parent, so below an empty one all are empty
+        if self.inter.borrow().world.take().is_none() {
+            return;
+        }
+        for child in self.children() {
+            child.invalidate_world();
+        }
+    }
 }
 
 impl SceneObject {
-    pub fn add_component<T>(&self, component: T)
-    where
-        T: ComponentPacker,
-    {
-        self.inter.borrow_mut().components.add_component(component);
+    /// Adds `component` after the others, `None` if `T` allows only one and there is one.
+    pub fn add_component<T: Component>(&self, component: T) -> Option<ComponentHandle> {
+        self.inter.borrow_mut().components.add_component(component)
     }
 
-    pub fn get_component(&self, identifier: ComponentIdentifier) -> Option<ComponentHandle> {
+    /// Detaches and removes `component`, returns whether it was on this object.
+    #[allow(dead_code)]
+    pub fn remove_component(&self, component: &ComponentHandle) -> bool {
+        self.inter
+            .borrow_mut()
+            .components
+            .remove_component(component)
+    }
+
+    /// First component of type `T`.
+    pub fn get_component<T: Component>(&self) -> Option<ComponentHandle> {
+        let inter = &self.inter.borrow().components;
+        inter.get_component::<T>()
+    }
+
+    /// Every component of type `T`, in the order they were added.
+    #[allow(dead_code)]
+    pub fn get_components<T: Component>(&self) -> Vec<ComponentHandle> {
         let inter = &self.inter.borrow().components;
-        inter.get_component(identifier)
+        inter.get_components::<T>()
+    }
+
+    /// Calls `f` with the `T` component of this object, if it has one.
+    pub fn with_component<T: Component, R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let handle = self.get_component::<T>()?;
+        let component = handle.get::<T>()?;
+        Some(f(&component))
     }
 }
 
@@ -298,6 +906,239 @@ impl SceneObject {
         ui.text_disabled(&inter.uuid);
         ui::input_text(ui, "Name:", &mut inter.name, Some("Name"));
         ui.separator();
+        let transform = inter.components.transform.clone();
         inter.components.gui(ui);
+        if inter.components.transform != transform {
+            drop(inter);
+            self.transform_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_abs_diff_eq, vec3};
+
+    use super::{component::MeshFilter, history::MAX_COMMANDS, *};
+    use crate::app::{
+        assets::{AssetDatabase, AssetHandle, AssetLocation},
+        renderer::{
+            headless::HeadlessRenderer,
+            model::{resource_path, Material, Mesh},
+        },
+    };
+
+    #[test]
+    fn destroy_frees_subtree_and_components() {
+        let scene = Scene::new("Scene");
+        let parent = SceneObject::new("Parent");
+        let child = SceneObject::new("Child");
+        let filter = child.add_component(MeshFilter::new()).unwrap();
+        parent.add_child(child.clone());
+        scene.root().add_child(parent.clone());
+
+        let weak_parent = Rc::downgrade(&parent.inter);
+        let weak_child = Rc::downgrade(&child.inter);
+        let weak_filter = filter.downgrade();
+        drop((child, filter));
+        parent.destroy();
+        drop(parent);
+
+        assert_eq!(weak_parent.strong_count(), 0);
+        assert_eq!(weak_child.strong_count(), 0);
+        assert_eq!(weak_filter.strong_count(), 0);
+        assert!(scene.root().children().is_empty());
+    }
+
+    /// Needs a GPU or a software adapter, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn destroy_and_evicted_removal_release_gpu_assets() {
+        let renderer = pollster::block_on(HeadlessRenderer::with_fallback_adapter(1, 1)).unwrap();
+        let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+        let location = |ident| AssetLocation::from_ident(ident).unwrap();
+        let mesh: AssetHandle<Mesh> = asset_db
+            .resolve(location("res:cube.obj#Cube_Finished_Cube.001"))
+            .unwrap();
+        let material: AssetHandle<Material> = asset_db
+            .resolve(location("res:cube.obj#Material.001"))
+            .unwrap();
+        let counts = || {
+            (
+                Rc::strong_count(&mesh.asset),
+                Rc::strong_count(&material.asset),
+            )
+        };
+        // Held by the database, the loaded model and this test
+        let unused = counts();
+        let used_by = |filters: usize| (unused.0 + filters, unused.1 + filters);
+
+        let scene = Scene::new("Scene");
+        let destroyed = SceneObject::new("Destroyed");
+        let removed = SceneObject::new("Removed");
+        for object in [&destroyed, &removed] {
+            let filter = MeshFilter::with_material(mesh.clone(), material.clone());
+            object.add_component(filter).unwrap();
+        }
+        scene.root().add_child(destroyed.clone());
+        scene.add_child(&scene.root(), removed.clone());
+        assert_eq!(counts(), used_by(2));
+
+        destroyed.destroy();
+        drop(destroyed);
+        assert_eq!(counts(), used_by(1));
+
+        scene.remove_object(&removed);
+        drop(removed);
+        // Kept so the removal can be undone
+        assert_eq!(counts(), used_by(1));
+        for i in 0..MAX_COMMANDS {
+            scene.add_child(&scene.root(), SceneObject::new(format!("Filler {i}")));
+        }
+        assert_eq!(counts(), unused);
+
+        // Nothing in the scene keeps the buffers alive past the database
+        let weak_mesh = Rc::downgrade(&mesh.asset);
+        let weak_material = Rc::downgrade(&material.asset);
+        drop((mesh, material, asset_db));
+        assert_eq!(weak_mesh.strong_count(), 0);
+        assert_eq!(weak_material.strong_count(), 0);
+    }
+
+    /// Needs a GPU or a software adapter, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn broken_models_are_reported_not_panicked_on() {
+        let renderer = pollster::block_on(HeadlessRenderer::with_fallback_adapter(1, 1)).unwrap();
+        let asset_db = AssetDatabase::new(renderer.shared_device(), renderer.shared_queue());
+
+        let error = Scene::load(resource_path("tests/missing_mtl.yaml"), &asset_db)
+            .err()
+            .unwrap();
+        assert!(
+            format!("{error:#}").contains("materials of tests/missing_mtl.obj"),
+            "{error:#}"
+        );
+
+        // Positions are all an obj needs
+        let location = AssetLocation::from_ident("res:tests/no_uvs.obj#Triangle").unwrap();
+        assert!(asset_db.resolve::<Mesh>(location).is_ok());
+    }
+
+    fn translation(position: Vector3<f32>) -> Transform {
+        let mut transform = Transform::new();
+        transform.set_position(position);
+        transform
+    }
+
+    fn object_at(name: &str, position: Vector3<f32>) -> SceneObject {
+        let object = SceneObject::new(name);
+        object.set_transform(translation(position));
+        object
+    }
+
+    #[test]
+    fn moving_an_ancestor_updates_cached_world_matrices() {
+        let grandparent = object_at("Grandparent", vec3(0.0, 0.0, 0.0));
+        let parent = object_at("Parent", vec3(0.0, 1.0, 0.0));
+        let child = object_at("Child", vec3(0.0, 0.0, 1.0));
+        grandparent.add_child(parent.clone());
+        parent.add_child(child.clone());
+        assert_eq!(child.world_position(), vec3(0.0, 1.0, 1.0));
+
+        grandparent.set_transform(translation(vec3(5.0, 0.0, 0.0)));
+        assert_eq!(child.world_position(), vec3(5.0, 1.0, 1.0));
+
+        // Only the parent is cached when the grandparent moves again
+        parent.set_transform(translation(vec3(0.0, 2.0, 0.0)));
+        assert_eq!(parent.world_position(), vec3(5.0, 2.0, 0.0));
+        grandparent.set_transform(Transform::new());
+        assert_eq!(parent.world_position(), vec3(0.0, 2.0, 0.0));
+        assert_eq!(child.world_position(), vec3(0.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn set_parent_invalidates_the_moved_subtree() {
+        let first = object_at("First", vec3(1.0, 0.0, 0.0));
+        let second = object_at("Second", vec3(0.0, 2.0, 0.0));
+        let child = object_at("Child", vec3(0.0, 0.0, 3.0));
+        let grandchild = object_at("Grandchild", vec3(0.0, 0.0, 0.0));
+        child.add_child(grandchild.clone());
+
+        child.set_parent(&first).unwrap();
+        assert_eq!(grandchild.world_position(), vec3(1.0, 0.0, 3.0));
+        child.set_parent(&second).unwrap();
+        assert_eq!(grandchild.world_position(), vec3(0.0, 2.0, 3.0));
+        child.detach();
+        assert_eq!(grandchild.world_position(), vec3(0.0, 0.0, 3.0));
+    }
+
+    #[test]
+    fn set_world_position_accounts_for_the_parent_transform() {
+        let parent = SceneObject::new("Parent");
+        parent.set_transform(Transform::from_parts(
+            vec3(1.0, 2.0, 3.0),
+            Transform::euler_rotation(vec3(20.0, 90.0, -40.0)),
+            vec3(2.0, 3.0, 0.5),
+        ));
+        let child = object_at("Child", vec3(1.0, 1.0, 1.0));
+        parent.add_child(child.clone());
+        assert!(child.world_position() != vec3(1.0, 1.0, 1.0));
+
+        let target = vec3(-4.0, 7.0, 2.5);
+        child.set_world_position(target);
+        assert_abs_diff_eq!(child.world_position(), target, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn set_parent_leaves_one_parent() {
+        let first = SceneObject::new("First");
+        let second = SceneObject::new("Second");
+        let child = SceneObject::new("Child");
+        child.set_parent(&first).unwrap();
+        child.set_parent(&second).unwrap();
+
+        assert!(first.children().is_empty());
+        assert!(second.children() == [child.clone()]);
+        assert!(child.parent() == Some(second));
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let parent = SceneObject::new("Parent");
+        let child = SceneObject::new("Child");
+        let grandchild = SceneObject::new("Grandchild");
+        parent.add_child(child.clone());
+        child.add_child(grandchild.clone());
+
+        assert!(parent.set_parent(&parent).is_err());
+        assert!(parent.set_parent(&grandchild).is_err());
+        assert!(!parent.has_parent());
+        assert!(parent.children() == [child.clone()]);
+        assert!(grandchild.parent() == Some(child));
+    }
+
+    #[test]
+    fn removed_object_lives_until_its_command_is_evicted() {
+        let scene = Scene::new("Scene");
+        let object = SceneObject::new("Object");
+        let filter = object.add_component(MeshFilter::new()).unwrap();
+        scene.add_child(&scene.root(), object.clone());
+        scene.remove_object(&object);
+
+        let weak_object = Rc::downgrade(&object.inter);
+        let weak_filter = filter.downgrade();
+        drop((object, filter));
+        // Kept so the removal can be undone
+        assert!(weak_object.strong_count() > 0);
+        scene.undo();
+        assert_eq!(scene.root().children().len(), 1);
+        scene.redo();
+
+        for i in 0..MAX_COMMANDS {
+            scene.add_child(&scene.root(), SceneObject::new(format!("Filler {i}")));
+        }
+        assert_eq!(weak_object.strong_count(), 0);
+        assert_eq!(weak_filter.strong_count(), 0);
     }
 }
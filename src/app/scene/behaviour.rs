@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use crate::app::{assets::AssetDatabase, input::Input, scripting::ContextGuard};
+
+use super::{component::ComponentHandle, Scene, SceneObject};
+
+/// Rate fixed updates run at.
+pub const FIXED_TIMESTEP: Duration = Duration::from_micros(16_667);
+/// Fixed updates run per frame at most, a long frame lets the simulation fall behind
+/// instead of taking even longer to catch up.
+const MAX_FIXED_STEPS: u32 = 5;
+
+/// What a component sees of the world from its gameplay hooks.
+#[allow(dead_code)]
+pub struct BehaviourContext<'a> {
+    /// Object owning the component
+    pub object: SceneObject,
+    pub scene: &'a Scene,
+    pub assets: &'a AssetDatabase,
+    pub input: &'a Input,
+    /// Time since the last update, `FIXED_TIMESTEP` in fixed updates
+    pub dt: Duration,
+    destroyed: &'a mut Vec<SceneObject>,
+}
+
+impl BehaviourContext<'_> {
+    /// Destroys `object` and its children once every component had its turn this frame.
+    #[allow(dead_code)]
+    pub fn destroy(&mut self, object: &SceneObject) {
+        if !self.destroyed.contains(object) {
+            self.destroyed.push(object.clone());
+        }
+    }
+}
+
+impl Scene {
+    /// Copy of the scene for behaviours to run on, the editor scene and its history stay
+    /// as they are. Objects keep their ids, so the selection carries over.
+    pub fn play(&self, assets: &AssetDatabase) -> anyhow::Result<Scene> {
+        let copy = Scene::from_yaml(&self.to_yaml()?, assets)?;
+        copy.inter.borrow_mut().playing = true;
+        copy.select(self.inter.borrow().selected.clone());
+        Ok(copy)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.inter.borrow().playing
+    }
+
+    /// Runs the gameplay hooks of every component for a frame of `dt`. Components added
+    /// since the last frame start first, then fixed updates catch up and updates run last.
+    /// Scripts changed on disk are reloaded before any of them. Does nothing unless the scene
+    /// is playing.
+    pub fn update(&self, dt: Duration, assets: &AssetDatabase, input: &Input) {
+        if !self.is_playing() {
+            return;
+        }
+        assets.reload_changed_scripts();
+        let _context = ContextGuard::enter(self, input);
+        let steps = {
+            let mut inter = self.inter.borrow_mut();
+            inter.fixed_time += dt;
+            let steps = (inter.fixed_time.as_nanos() / FIXED_TIMESTEP.as_nanos()) as u32;
+            inter.fixed_time -= FIXED_TIMESTEP * steps;
+            if steps > MAX_FIXED_STEPS {
+                inter.fixed_time = Duration::ZERO;
+            }
+            steps.min(MAX_FIXED_STEPS)
+        };
+
+        let mut destroyed = vec![];
+        let mut run = |dt: Duration, hook: fn(&ComponentHandle, &mut BehaviourContext)| {
+            // Collected up front, hooks may change the hierarchy and components
+            for object in self.objects().collect::<Vec<_>>() {
+                let components = object.inter.borrow().components.all();
+                for component in components {
+                    let mut ctx = BehaviourContext {
+                        object: object.clone(),
+                        scene: self,
+                        assets,
+                        input,
+                        dt,
+                        destroyed: &mut destroyed,
+                    };
+                    hook(&component, &mut ctx);
+                }
+            }
+        };
+        run(dt, ComponentHandle::start);
+        for _ in 0..steps {
+            run(FIXED_TIMESTEP, ComponentHandle::fixed_update);
+        }
+        run(dt, ComponentHandle::update);
+
+        while !destroyed.is_empty() {
+            for object in std::mem::take(&mut destroyed) {
+                self.destroy_object(&object, assets, input, &mut destroyed);
+            }
+        }
+    }
+
+    /// Ends playing, every started component gets its `on_destroy`, children first, then all
+    /// objects are torn down. The scene must not be used afterwards.
+    pub fn stop(&self, assets: &AssetDatabase, input: &Input) {
+        if !self.is_playing() {
+            return;
+        }
+        let _context = ContextGuard::enter(self, input);
+        let root = self.root();
+        // Everything goes, objects the hooks ask to destroy included
+        self.run_on_destroy(&root, assets, input, &mut vec![]);
+        root.destroy();
+        self.inter.borrow_mut().playing = false;
+    }
+
+    /// Runs `on_destroy` for the subtree of `object`, children first, then destroys it.
+    /// Objects the hooks ask to destroy in turn are pushed to `destroyed`.
+    fn destroy_object(
+        &self,
+        object: &SceneObject,
+        assets: &AssetDatabase,
+        input: &Input,
+        destroyed: &mut Vec<SceneObject>,
+    ) {
+        if object == &self.root() {
+            log::warn!("The scene root can not be destroyed");
+            return;
+        }
+        // Already gone with an ancestor destroyed before
+        if !object.has_parent() {
+            return;
+        }
+        self.run_on_destroy(object, assets, input, destroyed);
+        object.destroy();
+        self.validate_selection();
+    }
+
+    fn run_on_destroy(
+        &self,
+        object: &SceneObject,
+        assets: &AssetDatabase,
+        input: &Input,
+        destroyed: &mut Vec<SceneObject>,
+    ) {
+        for child in object.children() {
+            self.run_on_destroy(&child, assets, input, destroyed);
+        }
+        let components = object.inter.borrow().components.all();
+        for component in components {
+            let mut ctx = BehaviourContext {
+                object: object.clone(),
+                scene: self,
+                assets,
+                input,
+                dt: Duration::ZERO,
+                destroyed: &mut *destroyed,
+            };
+            component.destroy(&mut ctx);
+        }
+    }
+}
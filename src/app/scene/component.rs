@@ -1,29 +1,127 @@
+mod camera;
+mod light;
 mod mesh_filter;
+mod registry;
+mod script;
 mod transform;
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    any::Any,
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::BTreeMap,
+    rc::Rc,
+};
 
+pub use camera::*;
 use imgui::Ui;
+pub use light::*;
 pub use mesh_filter::*;
-use serde::{Deserialize, Serialize};
+pub use registry::*;
+pub use script::*;
+use serde::{
+    de::DeserializeOwned, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer,
+};
 pub use transform::*;
 
-use super::SceneObject;
+use super::{BehaviourContext, SceneObject, WeakSceneObject};
 
-#[derive(Serialize, Deserialize, PartialEq)]
-pub enum Component {
-    MeshFilter(MeshFilter),
+/// Data attached to a `SceneObject`. Scenes store components under their `IDENT`, a type
+/// has to be registered with `register_component` for scenes containing it to load.
+pub trait Component: Clone + Default + Serialize + DeserializeOwned + 'static {
+    const IDENT: ComponentIdentifier;
+    /// Whether an object can have more than one component of this type
+    const MULTIPLE: bool = false;
+
+    /// Draws the inspector of the component.
+    fn gui(&mut self, ui: &Ui);
+
+    /// Called when the component is added to `object`, or moved to it by undo or loading.
+    fn attach(&mut self, _object: &SceneObject) {}
+
+    /// Called when the component is removed from its object, through the inspector or by
+    /// destroying the object.
+    fn detach(&mut self) {}
+
+    // Gameplay hooks, only called while the scene plays. The component is borrowed while
+    // they run, so it can reach its siblings through `ctx.object` but finds itself busy:
+    // `ComponentHandle::get` returns `None` for it and it has to use `self` instead.
+
+    /// Called before the first update the component takes part in.
+    fn on_start(&mut self, _ctx: &mut BehaviourContext) {}
+
+    /// Called every frame.
+    fn on_update(&mut self, _ctx: &mut BehaviourContext) {}
+
+    /// Called at a fixed rate, zero or more times per frame.
+    fn on_fixed_update(&mut self, _ctx: &mut BehaviourContext) {}
+
+    /// Called when a started component's object is destroyed through the context.
+    fn on_destroy(&mut self, _ctx: &mut BehaviourContext) {}
+}
+
+/// Object safe side of `Component`, implemented for every component type.
+pub trait DynComponent {
+    fn ident(&self) -> ComponentIdentifier;
+    fn gui(&mut self, ui: &Ui);
+    fn attach(&mut self, object: &SceneObject);
+    fn detach(&mut self);
+    fn on_start(&mut self, ctx: &mut BehaviourContext);
+    fn on_update(&mut self, ctx: &mut BehaviourContext);
+    fn on_fixed_update(&mut self, ctx: &mut BehaviourContext);
+    fn on_destroy(&mut self, ctx: &mut BehaviourContext);
+    fn clone_box(&self) -> Box<dyn DynComponent>;
+    fn to_value(&self) -> Result<serde_yaml::Value, serde_yaml::Error>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-pub trait ComponentPacker {
-    fn pack(self) -> Component;
+impl<T: Component> DynComponent for T {
+    fn ident(&self) -> ComponentIdentifier {
+        T::IDENT
+    }
+    fn gui(&mut self, ui: &Ui) {
+        Component::gui(self, ui)
+    }
+    fn attach(&mut self, object: &SceneObject) {
+        Component::attach(self, object)
+    }
+    fn detach(&mut self) {
+        Component::detach(self)
+    }
+    fn on_start(&mut self, ctx: &mut BehaviourContext) {
+        Component::on_start(self, ctx)
+    }
+    fn on_update(&mut self, ctx: &mut BehaviourContext) {
+        Component::on_update(self, ctx)
+    }
+    fn on_fixed_update(&mut self, ctx: &mut BehaviourContext) {
+        Component::on_fixed_update(self, ctx)
+    }
+    fn on_destroy(&mut self, ctx: &mut BehaviourContext) {
+        Component::on_destroy(self, ctx)
+    }
+    fn clone_box(&self) -> Box<dyn DynComponent> {
+        Box::new(self.clone())
+    }
+    fn to_value(&self) -> Result<serde_yaml::Value, serde_yaml::Error> {
+        serde_yaml::to_value(self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 pub type ComponentIdentifier = &'static str;
 
-#[derive(PartialEq)]
 pub struct ComponentHandle {
-    inter: Rc<RefCell<Component>>, //TODO: Make to weak
+    inter: Rc<RefCell<Box<dyn DynComponent>>>,
+    /// Kept outside the cell, so lookups work while a component runs its hooks
+    ident: ComponentIdentifier,
+    /// Whether `on_start` ran
+    started: Rc<Cell<bool>>,
 }
 
 impl Serialize for ComponentHandle {
@@ -31,19 +129,9 @@ impl Serialize for ComponentHandle {
     where
         S: serde::Serializer,
     {
-        self.inter.borrow().serialize(serializer)
-    }
-}
-
-impl<'de> Deserialize<'de> for ComponentHandle {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let c = Component::deserialize(deserializer)?;
-        Ok(ComponentHandle {
-            inter: Rc::new(RefCell::new(c)),
-        })
+        use serde::ser::Error;
+        let value = self.inter.borrow().to_value().map_err(S::Error::custom)?;
+        value.serialize(serializer)
     }
 }
 
@@ -51,92 +139,352 @@ impl Clone for ComponentHandle {
     fn clone(&self) -> Self {
         Self {
             inter: Rc::clone(&self.inter),
+            ident: self.ident,
+            started: Rc::clone(&self.started),
         }
     }
 }
 
+impl PartialEq for ComponentHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inter, &other.inter)
+    }
+}
+
 impl ComponentHandle {
-    pub fn get(&self) -> &Rc<RefCell<Component>> {
-        &self.inter
+    fn new(component: Box<dyn DynComponent>) -> ComponentHandle {
+        ComponentHandle {
+            ident: component.ident(),
+            inter: Rc::new(RefCell::new(component)),
+            started: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Runs `on_start` if it did not run yet.
+    pub(super) fn start(&self, ctx: &mut BehaviourContext) {
+        if !self.started.replace(true) {
+            self.inter.borrow_mut().on_start(ctx);
+        }
+    }
+
+    pub(super) fn update(&self, ctx: &mut BehaviourContext) {
+        self.inter.borrow_mut().on_update(ctx);
+    }
+
+    pub(super) fn fixed_update(&self, ctx: &mut BehaviourContext) {
+        self.inter.borrow_mut().on_fixed_update(ctx);
+    }
+
+    /// Runs `on_destroy` if the component was started.
+    pub(super) fn destroy(&self, ctx: &mut BehaviourContext) {
+        if self.started.get() {
+            self.inter.borrow_mut().on_destroy(ctx);
+        }
+    }
+
+    pub fn ident(&self) -> ComponentIdentifier {
+        self.ident
+    }
+
+    #[cfg(test)]
+    pub(super) fn downgrade(&self) -> std::rc::Weak<RefCell<Box<dyn DynComponent>>> {
+        Rc::downgrade(&self.inter)
+    }
+
+    /// The component as a `T`, `None` if it is of another type or busy, like a component
+    /// looking itself up from its own hook.
+    pub fn get<T: Component>(&self) -> Option<Ref<'_, T>> {
+        let component = self.inter.try_borrow().ok()?;
+        Ref::filter_map(component, |c| c.as_any().downcast_ref()).ok()
+    }
+
+    /// Mutable access like `get`, `None` while the component is borrowed elsewhere.
+    #[allow(dead_code)]
+    pub fn get_mut<T: Component>(&self) -> Option<RefMut<'_, T>> {
+        let component = self.inter.try_borrow_mut().ok()?;
+        RefMut::filter_map(component, |c| c.as_any_mut().downcast_mut()).ok()
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize)]
 pub(super) struct ComponentContainer {
     pub(super) transform: Transform,
-    components: HashMap<String, ComponentHandle>,
+    /// In the order they were added, which is also the order they are saved in
+    #[serde(
+        serialize_with = "serialize_components",
+        deserialize_with = "deserialize_components"
+    )]
+    components: Vec<ComponentHandle>,
 
     #[serde(skip)]
-    object: Option<SceneObject>,
+    object: Option<WeakSceneObject>,
+}
+
+/// Saves components as a list of `ident: component` entries.
+fn serialize_components<S: Serializer>(
+    components: &[ComponentHandle],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(components.len()))?;
+    for component in components {
+        seq.serialize_element(&BTreeMap::from([(component.ident(), component)]))?;
+    }
+    seq.end()
+}
+
+/// Reads the list written by `serialize_components`, or a map from identifier to component
+/// as older scenes have it.
+fn deserialize_components<'de, D>(deserializer: D) -> Result<Vec<ComponentHandle>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    use serde_yaml::Value;
+    let entries = match Value::deserialize(deserializer)? {
+        Value::Mapping(map) => map.into_iter().collect(),
+        Value::Sequence(seq) => seq
+            .into_iter()
+            .map(|entry| match entry {
+                Value::Mapping(map) if map.len() == 1 => {
+                    Ok(map.into_iter().next().expect("Has one entry"))
+                }
+                _ => Err(D::Error::custom("Expected a single `ident: component` entry")),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Value::Null => vec![],
+        _ => return Err(D::Error::custom("Expected a list of components")),
+    };
+    entries
+        .into_iter()
+        .map(|(ident, value)| {
+            let ident = ident
+                .as_str()
+                .ok_or_else(|| D::Error::custom("Component identifiers must be strings"))?;
+            let component = deserialize_component(ident, value).map_err(D::Error::custom)?;
+            Ok(ComponentHandle::new(component))
+        })
+        .collect()
 }
 
 impl ComponentContainer {
     pub(super) fn empty() -> ComponentContainer {
         ComponentContainer {
-            components: HashMap::new(),
+            components: vec![],
             transform: Transform::default(),
             object: None,
         }
     }
 
-    pub(super) fn attach(&mut self, object: SceneObject) {
-        self.object = Some(object);
+    /// Deep copy that shares no components with `self`, attach it before use.
+    pub(super) fn duplicate(&self) -> ComponentContainer {
+        ComponentContainer {
+            transform: self.transform.clone(),
+            components: self
+                .components
+                .iter()
+                .map(|component| ComponentHandle::new(component.inter.borrow().clone_box()))
+                .collect(),
+            object: None,
+        }
+    }
+
+    /// Makes `object` the owner and attaches every component to it.
+    pub(super) fn attach(&mut self, object: &SceneObject) {
+        for component in self.components.iter() {
+            component.inter.borrow_mut().attach(object);
+        }
+        self.object = Some(object.downgrade());
+    }
+
+    /// Tears down and drops every component, the transform is kept.
+    pub(super) fn destroy(&mut self) {
+        for component in self.components.drain(..) {
+            component.inter.borrow_mut().detach();
+        }
+        self.object = None;
     }
 
     pub(super) fn gui(&mut self, ui: &Ui) {
         self.transform.gui(ui);
-        for (_, component) in self.components.iter_mut() {
+        let mut removed = None;
+        for (i, component) in self.components.iter().enumerate() {
+            // Components of the same type would share their widget ids otherwise
+            let _id = ui.push_id_usize(i);
             component.inter.borrow_mut().gui(ui);
+            if ui.small_button("Remove") {
+                removed = Some(component.clone());
+            }
+        }
+        if let Some(component) = removed {
+            self.remove_component(&component);
+        }
+
+        ui.separator();
+        if ui.button("Add Component") {
+            ui.open_popup("add_component_popup");
+        }
+        if let Some(_popup) = ui.begin_popup("add_component_popup") {
+            for (ident, multiple) in registered_components() {
+                let present = self.components.iter().any(|c| c.ident() == ident);
+                if ui
+                    .menu_item_config(ident)
+                    .enabled(multiple || !present)
+                    .build()
+                {
+                    if let Some(component) = create_component(ident) {
+                        self.insert(component);
+                    }
+                }
+            }
         }
     }
 
-    pub fn add_component<T>(&mut self, component: T)
-    where
-        T: ComponentPacker,
-    {
-        let mut comp = component.pack();
-        let ident = comp.ident().to_string();
-        comp.attach(self.object.as_ref().expect("Must be attached").clone());
-        let comp = comp.pack();
+    /// Adds `component` after the others. Returns `None` without adding it if the object
+    /// already has a `T` and `T` does not allow more than one.
+    pub fn add_component<T: Component>(&mut self, component: T) -> Option<ComponentHandle> {
+        if !T::MULTIPLE && self.get_component::<T>().is_some() {
+            log::warn!("Object already has a {} component", T::IDENT);
+            return None;
+        }
+        Some(self.insert(Box::new(component)))
+    }
 
-        self.components.insert(ident, comp);
+    fn insert(&mut self, component: Box<dyn DynComponent>) -> ComponentHandle {
+        let handle = ComponentHandle::new(component);
+        self.push(handle.clone());
+        handle
     }
-    pub fn get_component(&self, identifier: ComponentIdentifier) -> Option<ComponentHandle> {
-        if self.components.contains_key(identifier) {
-            let c = self.components.get(identifier).expect("testet with if");
-            Some(c.clone())
-        } else {
-            None
+
+    /// Adds `component` last, it is attached now if the owner is known and by `attach` if not.
+    fn push(&mut self, component: ComponentHandle) {
+        if let Some(object) = self.object.as_ref().and_then(WeakSceneObject::upgrade) {
+            component.inter.borrow_mut().attach(&object);
         }
+        self.components.push(component);
     }
-}
 
-impl Component {
-    fn gui(&mut self, ui: &Ui) {
-        match self {
-            Component::MeshFilter(mesh_filter) => mesh_filter.gui(ui),
+    /// Takes the transform and components of `other`, the current components are detached
+    /// and the new ones attached like when removing and adding them one by one.
+    pub(super) fn replace(&mut self, other: ComponentContainer) {
+        for component in self.all() {
+            self.remove_component(&component);
         }
-    }
-    fn ident(&self) -> &'static str {
-        match self {
-            Component::MeshFilter(_) => MeshFilter::IDENT,
+        self.transform = other.transform;
+        for component in other.components {
+            self.push(component);
         }
     }
-    fn pack(self) -> ComponentHandle {
-        ComponentHandle {
-            inter: Rc::new(RefCell::new(self)),
-        }
+
+    /// Detaches and removes `component`, returns whether it was on this object.
+    pub fn remove_component(&mut self, component: &ComponentHandle) -> bool {
+        let Some(index) = self.components.iter().position(|c| c == component) else {
+            return false;
+        };
+        self.components.remove(index).inter.borrow_mut().detach();
+        true
     }
 
-    fn attach(&mut self, object: SceneObject) {
-        match self {
-            Component::MeshFilter(filter) => filter.attach(object),
-        }
+    /// First component of type `T`.
+    pub fn get_component<T: Component>(&self) -> Option<ComponentHandle> {
+        self.components
+            .iter()
+            .find(|c| c.ident() == T::IDENT)
+            .cloned()
+    }
+
+    /// Every component, in order.
+    pub(super) fn all(&self) -> Vec<ComponentHandle> {
+        self.components.clone()
+    }
+
+    /// Every component of type `T`, in order.
+    pub fn get_components<T: Component>(&self) -> Vec<ComponentHandle> {
+        self.components
+            .iter()
+            .filter(|c| c.ident() == T::IDENT)
+            .cloned()
+            .collect()
     }
 }
 
-impl ComponentPacker for Component {
-    fn pack(self) -> Component {
-        self
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::scene::Scene;
+
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    struct Probe {
+        #[serde(skip)]
+        attached: Option<WeakSceneObject>,
+    }
+
+    impl Component for Probe {
+        const IDENT: ComponentIdentifier = "probe";
+
+        fn gui(&mut self, _ui: &Ui) {}
+
+        fn attach(&mut self, object: &SceneObject) {
+            self.attached = Some(object.downgrade());
+        }
+    }
+
+    #[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+    struct Health {
+        points: u32,
+    }
+
+    impl Component for Health {
+        const IDENT: ComponentIdentifier = "health";
+
+        fn gui(&mut self, _ui: &Ui) {}
+    }
+
+    #[test]
+    fn registered_component_round_trips_through_a_scene() {
+        register_component::<Health>();
+        let scene = Scene::new("Scene");
+        let player = SceneObject::new("Player");
+        player.add_component(Health { points: 7 }).unwrap();
+        scene.root().add_child(player);
+
+        let loaded: Scene = serde_yaml::from_str(&scene.to_yaml().unwrap()).unwrap();
+        let player = loaded.find_path("Scene/Player").unwrap();
+        let health = player.get_component::<Health>().unwrap();
+        assert_eq!(*health.get::<Health>().unwrap(), Health { points: 7 });
+    }
+
+    #[test]
+    fn unregistered_component_fails_to_load() {
+        let object = SceneObject::new("Object");
+        object.add_component(Probe::default()).unwrap();
+        let yaml = serde_yaml::to_string(&object).unwrap();
+        let error = serde_yaml::from_str::<SceneObject>(&yaml).err().unwrap();
+        assert!(
+            error.to_string().contains("Unknown component type probe"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn components_added_before_the_owner_attach_with_it() {
+        let mut container = ComponentContainer::empty();
+        let handle = container.add_component(Probe::default()).unwrap();
+        assert!(handle.get::<Probe>().unwrap().attached.is_none());
+
+        let object = SceneObject::new("Object");
+        container.attach(&object);
+        let attached = handle.get::<Probe>().unwrap().attached.clone();
+        assert!(attached.and_then(|o| o.upgrade()) == Some(object));
+    }
+
+    #[test]
+    fn busy_component_is_found_but_not_borrowed() {
+        let object = SceneObject::new("Object");
+        let handle = object.add_component(Probe::default()).unwrap();
+        // Like a hook running on the component
+        let _busy = handle.inter.borrow_mut();
+        let found = object.get_component::<Probe>().unwrap();
+        assert!(found == handle);
+        assert!(found.get::<Probe>().is_none());
     }
 }
@@ -0,0 +1,208 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector4};
+use imgui::{Drag, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{app::renderer::camera::OPENGL_TO_WGPU_MATRIX, gui::ui};
+
+use super::{Component, ComponentIdentifier};
+
+/// How a `Camera` maps view space to the screen.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Projection {
+    /// `fov` is the vertical field of view in degrees
+    Perspective { fov: f32 },
+    /// `size` is half the visible height in world units
+    Orthographic { size: f32 },
+}
+
+/// Camera looking along the forward (-Z) axis of its object.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Camera {
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+    /// x, y, width and height of the rendered area as fractions of the framebuffer
+    #[serde(default = "Camera::full_viewport")]
+    pub viewport: [f32; 4],
+    /// Inactive cameras are skipped when looking for the camera to render with
+    #[serde(default = "Camera::default_active")]
+    pub active: bool,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera::perspective(45.0, 0.1, 100.0)
+    }
+
+    pub fn perspective(fov: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            projection: Projection::Perspective { fov },
+            near,
+            far,
+            viewport: Self::full_viewport(),
+            active: true,
+        }
+    }
+
+    pub fn orthographic(size: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            projection: Projection::Orthographic { size },
+            ..Camera::perspective(45.0, near, far)
+        }
+    }
+
+    fn full_viewport() -> [f32; 4] {
+        [0.0, 0.0, 1.0, 1.0]
+    }
+
+    fn default_active() -> bool {
+        true
+    }
+
+    /// Projection into wgpu clip space for a viewport with the given aspect ratio.
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let proj = match self.projection {
+            Projection::Perspective { fov } => {
+                cgmath::perspective(cgmath::Deg(fov), aspect, self.near, self.far)
+            }
+            Projection::Orthographic { size } => cgmath::ortho(
+                -size * aspect,
+                size * aspect,
+                -size,
+                size,
+                self.near,
+                self.far,
+            ),
+        };
+        OPENGL_TO_WGPU_MATRIX * proj
+    }
+
+    /// View matrix of a camera placed by `world`, scale of the object is ignored.
+    pub fn view_matrix(world: &Matrix4<f32>) -> Matrix4<f32> {
+        let position = world * Vector4::unit_w();
+        let forward = (world * -Vector4::unit_z()).truncate().normalize();
+        let up = (world * Vector4::unit_y()).truncate().normalize();
+        Matrix4::look_to_rh(Point3::from_homogeneous(position), forward, up)
+    }
+
+    /// Viewport in pixels of a framebuffer of `size`, as x, y, width and height. The rect is
+    /// at least a pixel large and always inside the framebuffer.
+    pub fn viewport_rect(&self, size: [u32; 2]) -> [f32; 4] {
+        let [x, y, width, height] = self.viewport.map(|v| v.clamp(0.0, 1.0));
+        let (w, h) = (size[0].max(1) as f32, size[1].max(1) as f32);
+        let (x, y) = ((x * w).min(w - 1.0), (y * h).min(h - 1.0));
+        [
+            x,
+            y,
+            (width * w).clamp(1.0, w - x),
+            (height * h).clamp(1.0, h - y),
+        ]
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Component for Camera {
+    const IDENT: ComponentIdentifier = "camera";
+
+    fn gui(&mut self, ui: &Ui) {
+        let open = ui
+            .tree_node_config("camera_gui_tree_node")
+            .default_open(true)
+            .label::<String, String>("Camera".to_string())
+            .framed(true)
+            .push();
+        if open.is_some() {
+            ui::text_label(ui, "Active:");
+            ui.checkbox("##camera_input_active", &mut self.active);
+
+            ui::text_label(ui, "Projection:");
+            let mut index = match self.projection {
+                Projection::Perspective { .. } => 0,
+                Projection::Orthographic { .. } => 1,
+            };
+            if ui.combo_simple_string(
+                "##camera_input_projection",
+                &mut index,
+                &["Perspective", "Orthographic"],
+            ) {
+                self.projection = match index {
+                    0 => Projection::Perspective { fov: 45.0 },
+                    _ => Projection::Orthographic { size: 5.0 },
+                };
+            }
+            match &mut self.projection {
+                Projection::Perspective { fov } => {
+                    ui::text_label(ui, "Field of View:");
+                    Drag::new("##camera_input_fov")
+                        .speed(0.1)
+                        .range(1.0, 179.0)
+                        .build(ui, fov);
+                }
+                Projection::Orthographic { size } => {
+                    ui::text_label(ui, "Size:");
+                    Drag::new("##camera_input_size")
+                        .speed(0.05)
+                        .range(0.01, f32::MAX)
+                        .build(ui, size);
+                }
+            }
+
+            ui::text_label(ui, "Near:");
+            Drag::new("##camera_input_near")
+                .speed(0.01)
+                .range(0.001, self.far)
+                .build(ui, &mut self.near);
+            ui::text_label(ui, "Far:");
+            Drag::new("##camera_input_far")
+                .speed(0.5)
+                .range(self.near, f32::MAX)
+                .build(ui, &mut self.far);
+
+            ui::text_label(ui, "Viewport:");
+            Drag::new("##camera_input_viewport")
+                .speed(0.01)
+                .range(0.0, 1.0)
+                .build_array(ui, &mut self.viewport);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(viewport: [f32; 4], size: [u32; 2]) -> [f32; 4] {
+        Camera {
+            viewport,
+            ..Camera::new()
+        }
+        .viewport_rect(size)
+    }
+
+    #[test]
+    fn full_viewport_covers_framebuffer() {
+        assert_eq!(
+            rect(Camera::full_viewport(), [800, 600]),
+            [0.0, 0.0, 800.0, 600.0]
+        );
+    }
+
+    #[test]
+    fn viewport_stays_inside_framebuffer() {
+        for viewport in [
+            [1.0, 1.0, 1.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.75, 0.5, 0.5, 1.0],
+            [2.0, -1.0, 3.0, 0.0],
+        ] {
+            let [x, y, width, height] = rect(viewport, [800, 600]);
+            assert!(width >= 1.0 && height >= 1.0, "{viewport:?}");
+            assert!(x + width <= 800.0 && y + height <= 600.0, "{viewport:?}");
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector4};
+use imgui::{Drag, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::renderer::shadow::{ShadowProjection, ShadowView, MAX_RESOLUTION, MIN_RESOLUTION},
+    gui::ui,
+};
+
+use super::{Component, ComponentIdentifier};
+
+/// Maximum number of lights the main shader evaluates, has to match `shader.wgsl`.
+pub const MAX_LIGHTS: usize = 16;
+
+const LIGHT_DIRECTIONAL: f32 = 0.0;
+const LIGHT_POINT: f32 = 1.0;
+const LIGHT_SPOT: f32 = 2.0;
+
+const SHADOW_RESOLUTIONS: [u32; 4] = [256, 512, 1024, 2048];
+
+/// Shadow map settings of a light, bias is in shadow map depth and normal bias in world units.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct ShadowSettings {
+    pub cast_shadows: bool,
+    pub resolution: u32,
+    pub bias: f32,
+    pub normal_bias: f32,
+}
+
+/// Light shining along the forward (-Z) axis of its object, position is ignored.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct DirectionalLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    #[serde(default)]
+    pub shadows: ShadowSettings,
+    /// Distance from the camera up to which shadows are rendered
+    #[serde(default = "DirectionalLight::default_shadow_distance")]
+    pub shadow_distance: f32,
+}
+
+/// Light emitting in all directions from the position of its object.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct PointLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+}
+
+/// Cone shaped light along the forward (-Z) axis of its object, angles are in degrees.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct SpotLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    #[serde(default)]
+    pub shadows: ShadowSettings,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightRaw {
+    /// w is the kind of light
+    position: [f32; 4],
+    /// w is the range
+    direction: [f32; 4],
+    /// w is the intensity
+    color: [f32; 4],
+    /// cosine of the inner and outer cone angle
+    cone: [f32; 4],
+    /// first shadow map layer or -1, layer count, bias and normal bias
+    shadow: [f32; 4],
+}
+
+impl LightRaw {
+    fn new(kind: f32, world: &Matrix4<f32>, color: [f32; 3], intensity: f32) -> LightRaw {
+        let position = world * Vector4::unit_w();
+        let direction = (world * -Vector4::unit_z()).truncate().normalize();
+        LightRaw {
+            position: [position.x, position.y, position.z, kind],
+            direction: [direction.x, direction.y, direction.z, 0.0],
+            color: [color[0], color[1], color[2], intensity],
+            cone: [0.0; 4],
+            shadow: [-1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    fn with_shadow_bias(mut self, settings: &ShadowSettings) -> LightRaw {
+        self.shadow[2] = settings.bias;
+        self.shadow[3] = settings.normal_bias;
+        self
+    }
+
+    pub(crate) fn set_shadow_layers(&mut self, first: usize, count: usize) {
+        self.shadow[0] = first as f32;
+        self.shadow[1] = count as f32;
+    }
+}
+
+impl ShadowSettings {
+    pub fn new() -> ShadowSettings {
+        ShadowSettings {
+            cast_shadows: true,
+            resolution: 1024,
+            bias: 0.0005,
+            normal_bias: 0.02,
+        }
+    }
+
+    fn gui(&mut self, ui: &Ui, id: &str) {
+        ui::text_label(ui, "Cast Shadows:");
+        ui.checkbox(format!("##{id}_input_cast_shadows"), &mut self.cast_shadows);
+        if !self.cast_shadows {
+            return;
+        }
+        ui::text_label(ui, "Resolution:");
+        let mut index = SHADOW_RESOLUTIONS
+            .iter()
+            .position(|r| *r >= self.resolution)
+            .unwrap_or(SHADOW_RESOLUTIONS.len() - 1);
+        let labels = SHADOW_RESOLUTIONS.map(|r| r.to_string());
+        if ui.combo_simple_string(format!("##{id}_input_resolution"), &mut index, &labels) {
+            self.resolution = SHADOW_RESOLUTIONS[index];
+        }
+        ui::text_label(ui, "Bias:");
+        Drag::new(format!("##{id}_input_bias"))
+            .speed(0.0001)
+            .range(0.0, 0.1)
+            .display_format("%.4f")
+            .build(ui, &mut self.bias);
+        ui::text_label(ui, "Normal Bias:");
+        Drag::new(format!("##{id}_input_normal_bias"))
+            .speed(0.001)
+            .range(0.0, 1.0)
+            .build(ui, &mut self.normal_bias);
+    }
+
+    fn resolution(&self) -> u32 {
+        self.resolution.clamp(MIN_RESOLUTION, MAX_RESOLUTION)
+    }
+}
+
+impl DirectionalLight {
+    pub fn new() -> DirectionalLight {
+        DirectionalLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 3.0,
+            shadows: ShadowSettings::new(),
+            shadow_distance: Self::default_shadow_distance(),
+        }
+    }
+
+    fn default_shadow_distance() -> f32 {
+        50.0
+    }
+
+    pub fn to_raw(&self, world: &Matrix4<f32>) -> LightRaw {
+        LightRaw::new(LIGHT_DIRECTIONAL, world, self.color, self.intensity)
+            .with_shadow_bias(&self.shadows)
+    }
+
+    pub fn shadow_view(&self, world: &Matrix4<f32>) -> Option<ShadowView> {
+        self.shadows.cast_shadows.then(|| ShadowView {
+            projection: ShadowProjection::Directional {
+                direction: (world * -Vector4::unit_z()).truncate().normalize(),
+                distance: self.shadow_distance,
+            },
+            resolution: self.shadows.resolution(),
+        })
+    }
+}
+
+impl PointLight {
+    pub fn new() -> PointLight {
+        PointLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 30.0,
+            range: 10.0,
+        }
+    }
+
+    pub fn to_raw(&self, world: &Matrix4<f32>) -> LightRaw {
+        let mut raw = LightRaw::new(LIGHT_POINT, world, self.color, self.intensity);
+        raw.direction[3] = self.range;
+        raw
+    }
+}
+
+impl SpotLight {
+    pub fn new() -> SpotLight {
+        SpotLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 30.0,
+            range: 10.0,
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+            shadows: ShadowSettings::new(),
+        }
+    }
+
+    pub fn to_raw(&self, world: &Matrix4<f32>) -> LightRaw {
+        let mut raw = LightRaw::new(LIGHT_SPOT, world, self.color, self.intensity)
+            .with_shadow_bias(&self.shadows);
+        raw.direction[3] = self.range;
+        raw.cone = [
+            self.inner_angle.to_radians().cos(),
+            self.outer_angle.to_radians().cos(),
+            0.0,
+            0.0,
+        ];
+        raw
+    }
+
+    pub fn shadow_view(&self, world: &Matrix4<f32>) -> Option<ShadowView> {
+        let position = world * Vector4::unit_w();
+        self.shadows.cast_shadows.then(|| ShadowView {
+            projection: ShadowProjection::Spot {
+                position: Point3::from_homogeneous(position),
+                direction: (world * -Vector4::unit_z()).truncate().normalize(),
+                outer_angle: self.outer_angle,
+                range: self.range,
+            },
+            resolution: self.shadows.resolution(),
+        })
+    }
+}
+
+fn color_gui(ui: &Ui, id: &str, color: &mut [f32; 3], intensity: &mut f32) {
+    ui::text_label(ui, "Color:");
+    ui.color_edit3(format!("##{id}_input_color"), color);
+    ui::text_label(ui, "Intensity:");
+    Drag::new(format!("##{id}_input_intensity"))
+        .speed(0.05)
+        .range(0.0, f32::MAX)
+        .build(ui, intensity);
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Component for DirectionalLight {
+    const IDENT: ComponentIdentifier = "directional_light";
+
+    fn gui(&mut self, ui: &Ui) {
+        let open = ui
+            .tree_node_config("directional_light_gui_tree_node")
+            .default_open(true)
+            .label::<String, String>("Directional Light".to_string())
+            .framed(true)
+            .push();
+        if open.is_some() {
+            color_gui(ui, "directional_light", &mut self.color, &mut self.intensity);
+            self.shadows.gui(ui, "directional_light");
+            if self.shadows.cast_shadows {
+                ui::text_label(ui, "Shadow Distance:");
+                Drag::new("##directional_light_input_shadow_distance")
+                    .speed(0.5)
+                    .range(1.0, f32::MAX)
+                    .build(ui, &mut self.shadow_distance);
+            }
+        }
+    }
+}
+
+impl Component for PointLight {
+    const IDENT: ComponentIdentifier = "point_light";
+
+    fn gui(&mut self, ui: &Ui) {
+        let open = ui
+            .tree_node_config("point_light_gui_tree_node")
+            .default_open(true)
+            .label::<String, String>("Point Light".to_string())
+            .framed(true)
+            .push();
+        if open.is_some() {
+            color_gui(ui, "point_light", &mut self.color, &mut self.intensity);
+            ui::text_label(ui, "Range:");
+            Drag::new("##point_light_input_range")
+                .speed(0.1)
+                .range(0.0, f32::MAX)
+                .build(ui, &mut self.range);
+        }
+    }
+}
+
+impl Component for SpotLight {
+    const IDENT: ComponentIdentifier = "spot_light";
+
+    fn gui(&mut self, ui: &Ui) {
+        let open = ui
+            .tree_node_config("spot_light_gui_tree_node")
+            .default_open(true)
+            .label::<String, String>("Spot Light".to_string())
+            .framed(true)
+            .push();
+        if open.is_some() {
+            color_gui(ui, "spot_light", &mut self.color, &mut self.intensity);
+            ui::text_label(ui, "Range:");
+            Drag::new("##spot_light_input_range")
+                .speed(0.1)
+                .range(0.0, f32::MAX)
+                .build(ui, &mut self.range);
+            ui::text_label(ui, "Inner Angle:");
+            Drag::new("##spot_light_input_inner")
+                .speed(0.1)
+                .range(0.0, self.outer_angle)
+                .build(ui, &mut self.inner_angle);
+            ui::text_label(ui, "Outer Angle:");
+            Drag::new("##spot_light_input_outer")
+                .speed(0.1)
+                .range(self.inner_angle, 89.0)
+                .build(ui, &mut self.outer_angle);
+            self.shadows.gui(ui, "spot_light");
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use anyhow::anyhow;
+use serde_yaml::Value;
+
+use super::{
+    Camera, Component, ComponentIdentifier, DirectionalLight, DynComponent, MeshFilter, PointLight,
+    Script, SpotLight,
+};
+
+/// How to create a registered component type.
+#[derive(Clone, Copy)]
+struct Registration {
+    deserialize: fn(Value) -> Result<Box<dyn DynComponent>, serde_yaml::Error>,
+    create: fn() -> Box<dyn DynComponent>,
+    multiple: bool,
+}
+
+impl Registration {
+    fn of<T: Component>() -> Registration {
+        Registration {
+            deserialize: |value| Ok(Box::new(serde_yaml::from_value::<T>(value)?)),
+            create: || Box::<T>::default(),
+            multiple: T::MULTIPLE,
+        }
+    }
+}
+
+thread_local! {
+    // Component types scenes can be loaded with, keyed by identifier
+    static REGISTRY: RefCell<HashMap<ComponentIdentifier, Registration>> = RefCell::new(builtin());
+}
+
+fn builtin() -> HashMap<ComponentIdentifier, Registration> {
+    HashMap::from([
+        (MeshFilter::IDENT, Registration::of::<MeshFilter>()),
+        (
+            DirectionalLight::IDENT,
+            Registration::of::<DirectionalLight>(),
+        ),
+        (PointLight::IDENT, Registration::of::<PointLight>()),
+        (SpotLight::IDENT, Registration::of::<SpotLight>()),
+        (Camera::IDENT, Registration::of::<Camera>()),
+        (Script::IDENT, Registration::of::<Script>()),
+    ])
+}
+
+fn registration(ident: &str) -> Option<Registration> {
+    REGISTRY.with(|registry| registry.borrow().get(ident).copied())
+}
+
+/// Makes scenes containing `T` loadable, the engine's own components are always registered.
+pub fn register_component<T: Component>() {
+    REGISTRY.with(|registry| {
+        registry
+            .borrow_mut()
+            .insert(T::IDENT, Registration::of::<T>())
+    });
+}
+
+/// Identifiers of the registered types, sorted, and whether an object can have several.
+pub(super) fn registered_components() -> Vec<(ComponentIdentifier, bool)> {
+    let mut types = REGISTRY.with(|registry| {
+        registry
+            .borrow()
+            .iter()
+            .map(|(ident, registration)| (*ident, registration.multiple))
+            .collect::<Vec<_>>()
+    });
+    types.sort();
+    types
+}
+
+/// New default component of the type registered as `ident`.
+pub(super) fn create_component(ident: &str) -> Option<Box<dyn DynComponent>> {
+    registration(ident).map(|registration| (registration.create)())
+}
+
+/// Deserializes the component stored under `ident`.
+pub(super) fn deserialize_component(
+    ident: &str,
+    value: Value,
+) -> anyhow::Result<Box<dyn DynComponent>> {
+    let registration = registration(ident)
+        .ok_or_else(|| anyhow!("Unknown component type {ident}, is it registered?"))?;
+    // Scenes written before the registry tagged components with their type name
+    let value = match value {
+        Value::Tagged(tagged) => tagged.value,
+        value => value,
+    };
+    Ok((registration.deserialize)(value)?)
+}
@@ -0,0 +1,148 @@
+use imgui::{StyleColor, Ui};
+use rhai::{FuncArgs, FLOAT};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        assets::{AssetHandle, AssetLocation},
+        scene::BehaviourContext,
+        scripting::{ScriptInstance, ScriptSource},
+    },
+    gui::ui,
+};
+
+use super::{Component, ComponentIdentifier};
+
+/// Runs the Rhai script at `script`. The script defines any of `on_start()`, `on_update(dt)`,
+/// `on_fixed_update(dt)` and `on_destroy()`, with `this.object` being the owning object.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Script {
+    script: Option<AssetLocation>,
+
+    #[serde(skip)]
+    source: Option<AssetHandle<ScriptSource>>,
+    #[serde(skip)]
+    instance: Option<ScriptInstance>,
+    /// Last error and the source version it happened with, the hooks wait for a new version
+    #[serde(skip)]
+    error: Option<(String, Option<u32>)>,
+    #[serde(skip)]
+    path_input: Option<String>,
+}
+
+// Copies only get the script, they compile and start on their own
+impl Clone for Script {
+    fn clone(&self) -> Self {
+        Script {
+            script: self.script.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Script {
+    #[allow(dead_code)]
+    pub fn new(script: AssetLocation) -> Script {
+        Script {
+            script: Some(script),
+            ..Default::default()
+        }
+    }
+
+    fn source_version(&self) -> Option<u32> {
+        self.source.as_ref().map(|source| source.asset().version())
+    }
+
+    /// Loads and compiles the script if that did not happen since it last changed.
+    fn prepare(&mut self, ctx: &BehaviourContext) -> anyhow::Result<Option<&mut ScriptInstance>> {
+        let Some(location) = &self.script else {
+            return Ok(None);
+        };
+        if self.source.is_none() {
+            self.source = Some(ctx.assets.resolve::<ScriptSource>(location.clone())?);
+        }
+        let source = self.source.as_ref().expect("Resolved above").asset();
+        match &mut self.instance {
+            Some(instance) if instance.version() == source.version() => {}
+            Some(instance) => instance.recompile(&source)?,
+            None => self.instance = Some(ScriptInstance::compile(&source, &ctx.object)?),
+        }
+        Ok(self.instance.as_mut())
+    }
+
+    fn run(&mut self, ctx: &mut BehaviourContext, hook: &str, args: impl FuncArgs) {
+        if let Some((_, failed)) = &self.error {
+            if *failed == self.source_version() {
+                return;
+            }
+            self.error = None;
+        }
+        let result = self.prepare(ctx).and_then(|instance| match instance {
+            Some(instance) => instance.call(hook, args),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+
+    fn fail(&mut self, error: anyhow::Error) {
+        let message = format!("{error:#}");
+        let script = self
+            .script
+            .as_ref()
+            .map_or(String::new(), AssetLocation::to_ident);
+        log::error!("Script {script} failed: {message}");
+        self.error = Some((message, self.source_version()));
+    }
+}
+
+impl Component for Script {
+    const IDENT: ComponentIdentifier = "script";
+    const MULTIPLE: bool = true;
+
+    fn gui(&mut self, ui: &Ui) {
+        let open = ui
+            .tree_node_config("script_gui_tree_node")
+            .default_open(true)
+            .label::<String, String>("Script".to_string())
+            .framed(true)
+            .push();
+        if open.is_some() {
+            let path_input = self.path_input.get_or_insert_with(|| match &self.script {
+                Some(AssetLocation::Resource { path, .. }) => path.clone(),
+                _ => String::new(),
+            });
+            ui::input_text(ui, "Path:", path_input, Some("scripts/example.rhai"));
+            if ui.button("Load") {
+                let path = path_input.trim().to_string();
+                *self = Script {
+                    script: (!path.is_empty()).then(|| AssetLocation::resource(path)),
+                    ..Default::default()
+                };
+            }
+            if let Some((message, _)) = &self.error {
+                let _color = ui.push_style_color(StyleColor::Text, [1.0, 0.35, 0.35, 1.0]);
+                ui.text_wrapped(message);
+            }
+        }
+    }
+
+    fn on_start(&mut self, ctx: &mut BehaviourContext) {
+        self.run(ctx, "on_start", ());
+    }
+
+    fn on_update(&mut self, ctx: &mut BehaviourContext) {
+        let dt = ctx.dt.as_secs_f64() as FLOAT;
+        self.run(ctx, "on_update", (dt,));
+    }
+
+    fn on_fixed_update(&mut self, ctx: &mut BehaviourContext) {
+        let dt = ctx.dt.as_secs_f64() as FLOAT;
+        self.run(ctx, "on_fixed_update", (dt,));
+    }
+
+    fn on_destroy(&mut self, ctx: &mut BehaviourContext) {
+        self.run(ctx, "on_destroy", ());
+    }
+}
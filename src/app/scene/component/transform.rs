@@ -1,22 +1,229 @@
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::{
+    Deg, EuclideanSpace, Euler, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, Rotation,
+    Rotation3, Vector3, VectorSpace,
+};
 use imgui::{Drag, Ui};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{app::renderer::mesh::Vertex, gui::ui};
 
-#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Transform {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Vector3<f32>,
-    scale: cgmath::Vector3<f32>,
+    position: Vector3<f32>,
+    #[serde(
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+
+    /// Euler angles last entered in the inspector, so editing does not jump between
+    /// different angles describing the same rotation
+    #[serde(skip)]
+    euler_hint: Option<Vector3<f32>>,
+}
+
+/// Rotation as stored in scenes, older scenes used euler angles in degrees, which serde
+/// also reads from a list like any other vector.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredRotation {
+    Quaternion { x: f32, y: f32, z: f32, w: f32 },
+    Euler { x: f32, y: f32, z: f32 },
+    EulerList([f32; 3]),
+}
+
+fn serialize_rotation<S: Serializer>(
+    rotation: &Quaternion<f32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    StoredRotation::Quaternion {
+        x: rotation.v.x,
+        y: rotation.v.y,
+        z: rotation.v.z,
+        w: rotation.s,
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_rotation<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Quaternion<f32>, D::Error> {
+    Ok(match StoredRotation::deserialize(deserializer)? {
+        StoredRotation::Quaternion { x, y, z, w } => Quaternion::new(w, x, y, z).normalize(),
+        StoredRotation::Euler { x, y, z } | StoredRotation::EulerList([x, y, z]) => {
+            Transform::euler_rotation(cgmath::vec3(x, y, z))
+        }
+    })
+}
+
+impl PartialEq for Transform {
+    fn eq(&self, other: &Self) -> bool {
+        self.position == other.position
+            && self.rotation == other.rotation
+            && self.scale == other.scale
+    }
 }
 
 impl Transform {
     pub fn new() -> Transform {
+        Transform::from_parts(
+            cgmath::vec3(0.0, 0.0, 0.0),
+            Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            cgmath::vec3(1.0, 1.0, 1.0),
+        )
+    }
+
+    pub fn from_parts(
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Transform {
         Transform {
-            position: cgmath::vec3(0.0, 0.0, 0.0),
-            rotation: cgmath::vec3(0.0, 0.0, 0.0),
-            scale: cgmath::vec3(1.0, 1.0, 1.0),
+            position,
+            rotation,
+            scale,
+            euler_hint: None,
+        }
+    }
+
+    /// Rotation from euler angles in degrees.
+    pub fn euler_rotation(degrees: Vector3<f32>) -> Quaternion<f32> {
+        Quaternion::from(Euler::new(Deg(degrees.x), Deg(degrees.y), Deg(degrees.z)))
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.rotation
+    }
+
+    /// Rotation as euler angles in degrees.
+    pub fn euler_angles(&self) -> Vector3<f32> {
+        let euler = Euler::from(self.rotation);
+        cgmath::vec3(
+            Deg::from(euler.x).0,
+            Deg::from(euler.y).0,
+            Deg::from(euler.z).0,
+        )
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>) {
+        self.position = position;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+        self.rotation = rotation.normalize();
+    }
+
+    /// Local to parent space, scales first, then rotates and translates last.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Splits a translation * rotation * scale matrix back into its parts, shear is lost.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Transform {
+        let columns = [
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        ];
+        let scale = columns.map(|c| c.magnitude());
+        // A zero scaled axis has no direction left, fall back to the unrotated one
+        let units = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        let [x, y, z] = [0, 1, 2].map(|i| {
+            if scale[i] > f32::EPSILON {
+                columns[i] / scale[i]
+            } else {
+                units[i]
+            }
+        });
+        Transform::from_parts(
+            matrix.w.truncate(),
+            Quaternion::from(Matrix3::from_cols(x, y, z)).normalize(),
+            scale.into(),
+        )
+    }
+}
+
+// Helpers for gameplay code, the editor only needs a few of them
+#[allow(dead_code)]
+impl Transform {
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+    }
+
+    /// Direction of the local -Z axis in parent space, where cameras and lights look.
+    pub fn forward(&self) -> Vector3<f32> {
+        self.rotation.rotate_vector(-Vector3::unit_z())
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.rotation.rotate_vector(Vector3::unit_x())
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.rotation.rotate_vector(Vector3::unit_y())
+    }
+
+    /// Turns `forward` towards `target` with the local up as close to `up` as possible.
+    /// Nothing changes if `target` is at the position or straight along `up`.
+    pub fn look_at(&mut self, target: Point3<f32>, up: Vector3<f32>) {
+        let forward = target - Point3::from_vec(self.position);
+        let right = forward.cross(up);
+        if forward.magnitude2() <= f32::EPSILON || right.magnitude2() <= f32::EPSILON {
+            return;
+        }
+        let (forward, right) = (forward.normalize(), right.normalize());
+        let up = right.cross(forward);
+        self.set_rotation(Quaternion::from(Matrix3::from_cols(right, up, -forward)));
+    }
+
+    /// Rotates by `angle` around `axis`, both in parent space.
+    pub fn rotate(&mut self, axis: Vector3<f32>, angle: Deg<f32>) {
+        self.set_rotation(Quaternion::from_axis_angle(axis.normalize(), angle) * self.rotation);
+    }
+
+    /// Rotates around the line through `point` along `axis`, moving the position with it.
+    pub fn rotate_around(&mut self, point: Point3<f32>, axis: Vector3<f32>, angle: Deg<f32>) {
+        let rotation = Quaternion::from_axis_angle(axis.normalize(), angle);
+        let offset = self.position - point.to_vec();
+        self.position = point.to_vec() + rotation.rotate_vector(offset);
+        self.set_rotation(rotation * self.rotation);
+    }
+
+    /// Linear blend towards `other`, the rotation is normalized after blending.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform::from_parts(
+            self.position.lerp(other.position, t),
+            self.rotation.nlerp(self.nearest(other.rotation), t),
+            self.scale.lerp(other.scale, t),
+        )
+    }
+
+    /// Like `lerp` but the rotation turns at a constant speed.
+    pub fn slerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform::from_parts(
+            self.position.lerp(other.position, t),
+            self.rotation.slerp(self.nearest(other.rotation), t),
+            self.scale.lerp(other.scale, t),
+        )
+    }
+
+    /// `rotation` or its negation, whichever turns the shorter way from this rotation.
+    fn nearest(&self, rotation: Quaternion<f32>) -> Quaternion<f32> {
+        if self.rotation.dot(rotation) < 0.0 {
+            -rotation
+        } else {
+            rotation
         }
     }
 }
@@ -40,12 +247,20 @@ impl Transform {
             }
 
             ui::text_label(ui, "Rotation:");
-            let mut rot: [f32; 3] = self.rotation.into();
+            // Keep showing the entered angles while they still describe the rotation
+            let euler = self
+                .euler_hint
+                .filter(|hint| {
+                    Transform::euler_rotation(*hint).dot(self.rotation).abs() > 1.0 - 1e-6
+                })
+                .unwrap_or_else(|| self.euler_angles());
+            let mut rot: [f32; 3] = euler.into();
             if Drag::new("##transform_input_rot")
                 .speed(0.1)
                 .build_array(ui, &mut rot)
             {
-                self.rotation = rot.into();
+                self.rotation = Transform::euler_rotation(rot.into());
+                self.euler_hint = Some(rot.into());
             }
 
             ui::text_label(ui, "Size:");
@@ -66,23 +281,6 @@ impl Default for Transform {
     }
 }
 
-impl Transform {
-    pub(crate) fn to_raw(&self) -> TransformRaw {
-        TransformRaw {
-            model: (cgmath::Matrix4::from_nonuniform_scale(
-                self.scale.x,
-                self.scale.y,
-                self.scale.z,
-            ) * cgmath::Matrix4::from(cgmath::Quaternion::from(cgmath::Euler::new(
-                cgmath::Deg(self.rotation.x),
-                cgmath::Deg(self.rotation.y),
-                cgmath::Deg(self.rotation.z),
-            ))) * cgmath::Matrix4::from_translation(self.position))
-            .into(),
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct TransformRaw {
@@ -121,25 +319,91 @@ impl Vertex for TransformRaw {
     }
 }
 
-pub struct TransformStack {
-    stack: Vec<Matrix4<f32>>,
-}
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_abs_diff_eq, vec3};
 
-impl TransformStack {
-    pub fn new() -> Self {
-        Self { stack: vec![] }
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    /// Whether `a` and `b` describe the same rotation, a quaternion and its negation do.
+    fn same_rotation(a: Quaternion<f32>, b: Quaternion<f32>) -> bool {
+        a.dot(b).abs() > 1.0 - EPSILON
     }
-    pub fn push(&mut self, transform: TransformRaw) {
-        self.stack.push(Matrix4::from(transform.model));
+
+    fn load(rotation: &str) -> Transform {
+        let yaml = format!(
+            "position: {{x: 0.0, y: 0.0, z: 0.0}}\nrotation: {rotation}\nscale: {{x: 1.0, y: 1.0, z: 1.0}}"
+        );
+        serde_yaml::from_str(&yaml).unwrap()
     }
-    pub fn pop(&mut self) -> Option<TransformRaw> {
-        self.stack.pop().map(|f| TransformRaw { model: f.into() })
+
+    #[test]
+    fn legacy_euler_rotation_loads_as_quaternion() {
+        let expected = Quaternion::from_angle_y(Deg(90.0));
+        for rotation in ["{x: 0.0, y: 90.0, z: 0.0}", "[0.0, 90.0, 0.0]"] {
+            assert!(
+                same_rotation(load(rotation).rotation(), expected),
+                "{rotation}"
+            );
+        }
+
+        let stored = serde_yaml::to_string(&load("[10.0, 20.0, 30.0]")).unwrap();
+        let reloaded: Transform = serde_yaml::from_str(&stored).unwrap();
+        let expected = Transform::euler_rotation(vec3(10.0, 20.0, 30.0));
+        assert!(same_rotation(reloaded.rotation(), expected));
     }
-    pub fn eval(&self) -> TransformRaw {
-        let mat = self
-            .stack
-            .iter()
-            .fold(Matrix4::identity(), |acc, mat| acc * mat);
-        TransformRaw { model: mat.into() }
+
+    #[test]
+    fn look_at_points_forward_at_the_target() {
+        let mut transform = Transform::new();
+        transform.set_position(vec3(1.0, 2.0, 3.0));
+        let target = Point3::new(4.0, -1.0, 7.0);
+        transform.look_at(target, Vector3::unit_y());
+        let direction = (target.to_vec() - transform.position()).normalize();
+        assert_abs_diff_eq!(transform.forward(), direction, epsilon = EPSILON);
+        assert!(transform.up().y > 0.0);
+
+        // Straight up leaves no way to tell right from left
+        let before = transform.rotation();
+        transform.look_at(Point3::new(1.0, 5.0, 3.0), Vector3::unit_y());
+        assert_eq!(transform.rotation(), before);
+    }
+
+    #[test]
+    fn slerp_takes_the_shortest_path() {
+        let from = Transform::new();
+        let mut to = Transform::new();
+        to.set_rotation(Quaternion::from_angle_y(Deg(90.0)));
+        let mut negated = Transform::new();
+        negated.rotation = -to.rotation;
+
+        for other in [&to, &negated] {
+            assert!(same_rotation(
+                from.slerp(other, 0.0).rotation(),
+                from.rotation()
+            ));
+            assert!(same_rotation(
+                from.slerp(other, 1.0).rotation(),
+                to.rotation()
+            ));
+            let halfway = from.slerp(other, 0.5).rotation();
+            assert!(same_rotation(halfway, Quaternion::from_angle_y(Deg(45.0))));
+        }
+    }
+
+    #[test]
+    fn from_matrix_round_trips_matrix() {
+        let transform = Transform::from_parts(
+            vec3(1.0, -2.0, 3.0),
+            Transform::euler_rotation(vec3(30.0, -45.0, 60.0)),
+            vec3(2.0, 0.5, 3.0),
+        );
+        let split = Transform::from_matrix(&transform.matrix());
+        assert_abs_diff_eq!(split.position(), transform.position(), epsilon = EPSILON);
+        assert_abs_diff_eq!(split.scale(), transform.scale(), epsilon = EPSILON);
+        assert!(same_rotation(split.rotation(), transform.rotation()));
+        assert_abs_diff_eq!(split.matrix(), transform.matrix(), epsilon = EPSILON);
     }
 }
@@ -0,0 +1,204 @@
+use std::rc::Rc;
+
+use super::{ObjectState, SceneObject};
+
+/// Oldest commands are dropped beyond this.
+pub(super) const MAX_COMMANDS: usize = 256;
+
+/// Reversible change of a scene.
+pub(super) enum Command {
+    /// Name, transform or components of `object` changed
+    Modify {
+        object: SceneObject,
+        before: ObjectState,
+        after: ObjectState,
+    },
+    /// `object` moved between two places in the hierarchy, `None` is outside of the scene
+    Place {
+        object: SceneObject,
+        from: Option<(SceneObject, usize)>,
+        to: Option<(SceneObject, usize)>,
+    },
+    /// Commands applied in order and undone as one step
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    fn undo(&self) {
+        match self {
+            Command::Modify { object, before, .. } => object.restore(before),
+            Command::Place { object, from, .. } => place(object, from.as_ref()),
+            Command::Batch(commands) => commands.iter().rev().for_each(Command::undo),
+        }
+    }
+
+    fn redo(&self) {
+        match self {
+            Command::Modify { object, after, .. } => object.restore(after),
+            Command::Place { object, to, .. } => place(object, to.as_ref()),
+            Command::Batch(commands) => commands.iter().for_each(Command::redo),
+        }
+    }
+
+    /// Whether the command changes or places `object`.
+    fn references(&self, object: &SceneObject) -> bool {
+        match self {
+            Command::Modify { object: o, .. } | Command::Place { object: o, .. } => o == object,
+            Command::Batch(commands) => commands.iter().any(|c| c.references(object)),
+        }
+    }
+
+    /// Destroys the objects this command placed that are outside of the scene and that no
+    /// command in `remaining` can bring back. Removed objects are kept for undo until then.
+    fn release(self, remaining: &[Command]) {
+        match self {
+            Command::Place { object, .. } => {
+                if !object.has_parent() && !remaining.iter().any(|c| c.references(&object)) {
+                    object.destroy();
+                }
+            }
+            Command::Modify { .. } => {}
+            Command::Batch(commands) => commands.into_iter().for_each(|c| c.release(remaining)),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Command::Modify { after, .. } => format!("Edit {}", after.name),
+            Command::Place { object, from, to } => {
+                let action = match (from, to) {
+                    (None, _) => "Add",
+                    (_, None) => "Remove",
+                    _ => "Move",
+                };
+                format!("{action} {}", object.name())
+            }
+            Command::Batch(commands) => commands.first().map(Command::label).unwrap_or_default(),
+        }
+    }
+}
+
+fn place(object: &SceneObject, slot: Option<&(SceneObject, usize)>) {
+    object.detach_from_parent();
+    if let Some((parent, index)) = slot {
+        parent.insert_child(*index, object.clone());
+    }
+}
+
+/// Object whose edits are recorded, with the state it had when it was selected or after the
+/// last recorded edit.
+struct Tracked {
+    object: SceneObject,
+    state: ObjectState,
+    yaml: String,
+}
+
+impl Tracked {
+    fn new(object: &SceneObject) -> Tracked {
+        Tracked {
+            object: object.clone(),
+            state: object.state(),
+            yaml: object.state_yaml(),
+        }
+    }
+}
+
+/// Undo and redo stacks of a scene.
+#[derive(Default)]
+pub(super) struct History {
+    done: Vec<Command>,
+    undone: Vec<Command>,
+    tracked: Option<Tracked>,
+    /// Whether an edit was going on at the last `track`
+    editing: bool,
+}
+
+impl History {
+    /// Applies `command` and records it.
+    pub(super) fn execute(&mut self, command: Command) {
+        self.commit_tracked();
+        command.redo();
+        self.push(command);
+        // The command may have changed the tracked object, which is not an edit of its own
+        if let Some(tracked) = &mut self.tracked {
+            *tracked = Tracked::new(&tracked.object);
+        }
+    }
+
+    fn push(&mut self, command: Command) {
+        for undone in std::mem::take(&mut self.undone) {
+            undone.release(&self.done);
+        }
+        self.done.push(command);
+        if self.done.len() > MAX_COMMANDS {
+            let evicted = self.done.remove(0);
+            evicted.release(&self.done);
+        }
+    }
+
+    pub(super) fn undo(&mut self) -> bool {
+        self.commit_tracked();
+        let Some(command) = self.done.pop() else {
+            return false;
+        };
+        command.undo();
+        self.undone.push(command);
+        self.tracked = None;
+        true
+    }
+
+    pub(super) fn redo(&mut self) -> bool {
+        self.commit_tracked();
+        let Some(command) = self.undone.pop() else {
+            return false;
+        };
+        command.redo();
+        self.done.push(command);
+        self.tracked = None;
+        true
+    }
+
+    /// Records the changes made to `object` as one command when an edit ends, so a whole drag
+    /// or text input becomes one step. The object is only compared with its snapshot then and
+    /// when the selection changes, not every frame.
+    pub(super) fn track(&mut self, object: Option<&SceneObject>, editing: bool) {
+        let same = match (&self.tracked, object) {
+            (Some(tracked), Some(object)) => Rc::ptr_eq(&tracked.object.inter, &object.inter),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            self.commit_tracked();
+            self.tracked = object.map(Tracked::new);
+        } else if self.editing && !editing {
+            self.commit_tracked();
+        }
+        self.editing = editing;
+    }
+
+    fn commit_tracked(&mut self) {
+        let Some(tracked) = &mut self.tracked else {
+            return;
+        };
+        let yaml = tracked.object.state_yaml();
+        if yaml == tracked.yaml {
+            return;
+        }
+        let after = tracked.object.state();
+        let command = Command::Modify {
+            object: tracked.object.clone(),
+            before: std::mem::replace(&mut tracked.state, after.clone()),
+            after,
+        };
+        tracked.yaml = yaml;
+        self.push(command);
+    }
+
+    /// Labels of the undoable commands, oldest first, and of the redoable ones, next first.
+    pub(super) fn labels(&self) -> (Vec<String>, Vec<String>) {
+        (
+            self.done.iter().map(Command::label).collect(),
+            self.undone.iter().rev().map(Command::label).collect(),
+        )
+    }
+}
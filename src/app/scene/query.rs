@@ -0,0 +1,197 @@
+use super::{
+    component::{Component, ComponentHandle},
+    Scene, SceneObject,
+};
+
+/// Components `Scene::query` fetches together, a component type or a tuple of queries.
+/// A component type yields its untyped `ComponentHandle`, borrowed as the component with
+/// `ComponentHandle::get::<T>()`.
+pub trait Query {
+    type Item;
+
+    /// The components of `object`, `None` unless it has all of them.
+    fn fetch(object: &SceneObject) -> Option<Self::Item>;
+}
+
+impl<T: Component> Query for T {
+    type Item = ComponentHandle;
+
+    fn fetch(object: &SceneObject) -> Option<ComponentHandle> {
+        object.get_component::<T>()
+    }
+}
+
+macro_rules! tuple_query {
+    ($($name:ident),+) => {
+        impl<$($name: Query),+> Query for ($($name,)+) {
+            type Item = ($($name::Item,)+);
+
+            fn fetch(object: &SceneObject) -> Option<Self::Item> {
+                Some(($($name::fetch(object)?,)+))
+            }
+        }
+    };
+}
+
+tuple_query!(A, B);
+tuple_query!(A, B, C);
+tuple_query!(A, B, C, D);
+
+/// Walks an object and everything below it, parents before their children.
+pub struct Descendants {
+    stack: Vec<SceneObject>,
+}
+
+impl Iterator for Descendants {
+    type Item = SceneObject;
+
+    fn next(&mut self) -> Option<SceneObject> {
+        let object = self.stack.pop()?;
+        self.stack.extend(object.children().into_iter().rev());
+        Some(object)
+    }
+}
+
+impl Scene {
+    /// Every object of the scene, parents before their children.
+    pub fn objects(&self) -> Descendants {
+        self.root().descendants()
+    }
+
+    /// Every object having the components of `Q`, in hierarchy order, with their handles.
+    /// `scene.query::<(MeshFilter, Camera)>()` finds the objects having both.
+    pub fn query<Q: Query>(&self) -> impl Iterator<Item = (SceneObject, Q::Item)> {
+        self.objects()
+            .filter_map(|object| Q::fetch(&object).map(|item| (object, item)))
+    }
+
+    /// First object called `name`, in hierarchy order.
+    pub fn find_by_name(&self, name: &str) -> Option<SceneObject> {
+        self.objects().find(|object| object.name() == name)
+    }
+
+    /// The object at `path`, names from the root down separated by `/`, like
+    /// "Root/SceneObject 4/Child 2". Where siblings share a name the first one is taken.
+    pub fn find_path(&self, path: &str) -> Option<SceneObject> {
+        let (first, rest) = match path.split_once('/') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (path, None),
+        };
+        let root = Some(self.root()).filter(|root| root.name() == first)?;
+        match rest {
+            Some(rest) => root.find_path(rest),
+            None => Some(root),
+        }
+    }
+}
+
+impl SceneObject {
+    /// This object and everything below it, parents before their children.
+    pub fn descendants(&self) -> Descendants {
+        Descendants {
+            stack: vec![self.clone()],
+        }
+    }
+
+    /// First object below this one called `name`.
+    pub fn find_by_name(&self, name: &str) -> Option<SceneObject> {
+        self.descendants()
+            .skip(1)
+            .find(|object| object.name() == name)
+    }
+
+    /// The object at `path` relative to this one, like "Child 2/Subchild 1".
+    pub fn find_path(&self, path: &str) -> Option<SceneObject> {
+        path.split('/').try_fold(self.clone(), |object, name| {
+            object
+                .children()
+                .into_iter()
+                .find(|child| child.name() == name)
+        })
+    }
+
+    /// Names from the root down to this object, the path `Scene::find_path` takes.
+    pub fn path(&self) -> String {
+        let mut names = vec![self.name()];
+        let mut parent = self.parent();
+        while let Some(object) = parent {
+            names.push(object.name());
+            parent = object.parent();
+        }
+        names.reverse();
+        names.join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::scene::component::{Camera, MeshFilter, PointLight};
+
+    use super::*;
+
+    /// Scene/A with a camera, Scene/B with a camera and a light, Scene/B/C with a light and a
+    /// mesh filter.
+    fn scene() -> Scene {
+        let scene = Scene::new("Scene");
+        let a = SceneObject::new("A");
+        a.add_component(Camera::new());
+        let b = SceneObject::new("B");
+        b.add_component(Camera::new());
+        b.add_component(PointLight::new());
+        let c = SceneObject::new("C");
+        c.add_component(PointLight::new());
+        c.add_component(MeshFilter::new());
+        b.add_child(c);
+        scene.root().add_child(a);
+        scene.root().add_child(b);
+        scene
+    }
+
+    fn names<Q: Query>(scene: &Scene) -> Vec<String> {
+        scene
+            .query::<Q>()
+            .map(|(object, _)| object.name())
+            .collect()
+    }
+
+    #[test]
+    fn query_finds_objects_with_the_component() {
+        let scene = scene();
+        assert_eq!(names::<Camera>(&scene), ["A", "B"]);
+        assert_eq!(names::<PointLight>(&scene), ["B", "C"]);
+    }
+
+    #[test]
+    fn tuple_query_needs_every_component() {
+        let scene = scene();
+        assert_eq!(names::<(Camera, PointLight)>(&scene), ["B"]);
+        assert_eq!(names::<(PointLight, MeshFilter)>(&scene), ["C"]);
+        assert!(names::<(Camera, PointLight, MeshFilter)>(&scene).is_empty());
+
+        let (object, (camera, light)) = scene.query::<(Camera, PointLight)>().next().unwrap();
+        assert!(object.get_component::<Camera>() == Some(camera));
+        assert!(object.get_component::<PointLight>() == Some(light));
+    }
+
+    #[test]
+    fn find_path_follows_names_from_the_root() {
+        let scene = scene();
+        let found = |path: &str| scene.find_path(path).map(|object| object.name());
+        assert_eq!(found("Scene").as_deref(), Some("Scene"));
+        assert_eq!(found("Scene/B/C").as_deref(), Some("C"));
+        assert_eq!(found("Other/B/C"), None);
+        assert_eq!(found("B/C"), None);
+        assert_eq!(found("Scene//C"), None);
+        assert_eq!(found("Scene/B/"), None);
+        assert_eq!(found("Scene/"), None);
+        assert_eq!(found(""), None);
+    }
+
+    #[test]
+    fn path_round_trips_through_find_path() {
+        let scene = scene();
+        for object in scene.objects() {
+            assert!(scene.find_path(&object.path()) == Some(object));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+mod bindings;
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Context};
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+
+use super::{
+    input::Input,
+    renderer::model::resource_path,
+    scene::{Scene, SceneObject},
+};
+
+/// How often script files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+thread_local! {
+    static ENGINE: Engine = bindings::engine();
+    // World the native functions see while a script runs
+    static CONTEXT: RefCell<Option<ScriptContext>> = RefCell::new(None);
+}
+
+struct ScriptContext {
+    scene: Scene,
+    input: Input,
+}
+
+fn with_context<R>(f: impl FnOnce(&ScriptContext) -> R) -> Option<R> {
+    CONTEXT.with(|context| context.borrow().as_ref().map(f))
+}
+
+/// Makes `scene` and `input` what scripts see until it is dropped, set once for a frame of
+/// hooks rather than for every call.
+pub struct ContextGuard {
+    previous: Option<ScriptContext>,
+}
+
+impl ContextGuard {
+    pub fn enter(scene: &Scene, input: &Input) -> ContextGuard {
+        let context = ScriptContext {
+            scene: scene.clone(),
+            input: input.clone(),
+        };
+        ContextGuard {
+            previous: CONTEXT.with(|c| c.replace(Some(context))),
+        }
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|c| *c.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Source of a Rhai script, read again when the file changes.
+pub struct ScriptSource {
+    path: PathBuf,
+    source: String,
+    modified: Option<SystemTime>,
+    /// Bumped on every reload
+    version: u32,
+    last_check: Instant,
+}
+
+impl ScriptSource {
+    /// Loads the script at the resource `path`. Debug builds prefer the file in the source
+    /// tree over the copy the build makes, so edits to it are picked up without a rebuild.
+    pub fn load(path: &str) -> anyhow::Result<ScriptSource> {
+        let path = source_tree_path(path).unwrap_or_else(|| resource_path(path));
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read script {}", path.display()))?;
+        Ok(ScriptSource {
+            modified: modified_time(&path),
+            path,
+            source,
+            version: 0,
+            last_check: Instant::now(),
+        })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Reads the file again if it changed on disk, checking at most every `RELOAD_INTERVAL`.
+    pub fn reload_if_changed(&mut self) -> anyhow::Result<()> {
+        if self.last_check.elapsed() < RELOAD_INTERVAL {
+            return Ok(());
+        }
+        self.last_check = Instant::now();
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return Ok(());
+        }
+        // Taken before reading, a file that fails to read is not retried until it changes
+        self.modified = modified;
+        self.source = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to reload script {}", self.path.display()))?;
+        self.version += 1;
+        log::info!("Reloaded script {}", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(debug_assertions)]
+fn source_tree_path(path: &str) -> Option<PathBuf> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join(path);
+    path.exists().then_some(path)
+}
+
+#[cfg(not(debug_assertions))]
+fn source_tree_path(_path: &str) -> Option<PathBuf> {
+    None
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Compiled script and the state it keeps between calls.
+pub struct ScriptInstance {
+    ast: AST,
+    /// `this` in every hook, a map with the owning `object` and whatever the script adds.
+    /// Scripts only get weak handles to objects, so what they keep there creates no cycles.
+    this: Dynamic,
+    version: u32,
+}
+
+impl ScriptInstance {
+    pub fn compile(source: &ScriptSource, object: &SceneObject) -> anyhow::Result<ScriptInstance> {
+        let mut this = Map::new();
+        this.insert("object".into(), Dynamic::from(object.downgrade()));
+        Ok(ScriptInstance {
+            ast: compile(source)?,
+            this: this.into(),
+            version: source.version,
+        })
+    }
+
+    /// Runs the new source from now on, what the script stored on `this` is kept.
+    pub fn recompile(&mut self, source: &ScriptSource) -> anyhow::Result<()> {
+        self.ast = compile(source)?;
+        self.version = source.version;
+        Ok(())
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Calls the function `name` if the script defines it, inside a `ContextGuard`.
+    pub fn call(&mut self, name: &str, args: impl FuncArgs) -> anyhow::Result<()> {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(());
+        }
+        let result = ENGINE.with(|engine| {
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut self.this);
+            engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                &self.ast,
+                name,
+                args,
+            )
+        });
+        result.map(|_| ()).map_err(|e| anyhow!("In {name}: {e}"))
+    }
+}
+
+fn compile(source: &ScriptSource) -> anyhow::Result<AST> {
+    ENGINE
+        .with(|engine| engine.compile(&source.source))
+        .map_err(|e| anyhow!("{}: {e}", source.path.display()))
+}
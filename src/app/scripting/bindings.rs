@@ -0,0 +1,236 @@
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Point3, Vector3};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FLOAT};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::app::scene::{component::Transform, SceneObject, WeakSceneObject};
+
+use super::with_context;
+
+type Vec3 = Vector3<f32>;
+
+/// Engine with the sandbox limits set and every binding registered.
+pub(super) fn engine() -> Engine {
+    let mut engine = Engine::new();
+    // Scripts run inside the frame, a runaway loop must not hang the editor
+    engine.set_max_operations(1_000_000);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1 << 16);
+    engine.set_max_array_size(1 << 16);
+    engine.set_max_map_size(1 << 16);
+    engine.disable_symbol("eval");
+
+    engine.on_print(|text| log::info!("{text}"));
+    engine.on_debug(|text, _source, position| log::debug!("{text} ({position})"));
+    engine.register_fn("warn", |text: &str| log::warn!("{text}"));
+    engine.register_fn("error", |text: &str| log::error!("{text}"));
+
+    register_vec3(&mut engine);
+    register_scene_object(&mut engine);
+    register_input(&mut engine);
+    engine
+}
+
+fn register_vec3(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Vec3>("Vec3")
+        .register_fn("vec3", |x: FLOAT, y: FLOAT, z: FLOAT| {
+            cgmath::vec3(x as f32, y as f32, z as f32)
+        })
+        .register_get_set(
+            "x",
+            |v: &mut Vec3| v.x as FLOAT,
+            |v: &mut Vec3, x: FLOAT| v.x = x as f32,
+        )
+        .register_get_set(
+            "y",
+            |v: &mut Vec3| v.y as FLOAT,
+            |v: &mut Vec3, y: FLOAT| v.y = y as f32,
+        )
+        .register_get_set(
+            "z",
+            |v: &mut Vec3| v.z as FLOAT,
+            |v: &mut Vec3, z: FLOAT| v.z = z as f32,
+        )
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("-", |a: Vec3| -a)
+        .register_fn("*", |a: Vec3, s: FLOAT| a * s as f32)
+        .register_fn("*", |s: FLOAT, a: Vec3| a * s as f32)
+        .register_fn("/", |a: Vec3, s: FLOAT| a / s as f32)
+        .register_fn("dot", |a: Vec3, b: Vec3| a.dot(b) as FLOAT)
+        .register_fn("cross", |a: Vec3, b: Vec3| a.cross(b))
+        .register_fn("length", |v: &mut Vec3| v.magnitude() as FLOAT)
+        .register_fn("normalized", |v: &mut Vec3| {
+            if v.magnitude2() > f32::EPSILON {
+                v.normalize()
+            } else {
+                *v
+            }
+        })
+        .register_fn("to_string", |v: &mut Vec3| {
+            format!("({}, {}, {})", v.x, v.y, v.z)
+        })
+        .register_fn("to_debug", |v: &mut Vec3| {
+            format!("({}, {}, {})", v.x, v.y, v.z)
+        });
+}
+
+/// Objects reach scripts as weak handles, upgraded for every call. A script keeping one
+/// on `this` does not keep the object alive.
+type ScriptObject = WeakSceneObject;
+
+fn with<R>(
+    object: &ScriptObject,
+    f: impl FnOnce(&SceneObject) -> R,
+) -> Result<R, Box<EvalAltResult>> {
+    let object = object.upgrade().ok_or("The object was destroyed")?;
+    Ok(f(&object))
+}
+
+fn to_dynamic(object: Option<SceneObject>) -> Dynamic {
+    object.map_or(Dynamic::UNIT, |object| Dynamic::from(object.downgrade()))
+}
+
+fn register_scene_object(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptObject>("SceneObject")
+        .register_get("name", |o: &mut ScriptObject| with(o, SceneObject::name))
+        .register_get("path", |o: &mut ScriptObject| with(o, SceneObject::path))
+        .register_get("parent", |o: &mut ScriptObject| {
+            with(o, |o| to_dynamic(o.parent()))
+        })
+        .register_fn("find", |o: &mut ScriptObject, name: &str| {
+            with(o, |o| {
+                to_dynamic(if name.contains('/') {
+                    o.find_path(name)
+                } else {
+                    o.find_by_name(name)
+                })
+            })
+        })
+        .register_fn("==", |a: ScriptObject, b: ScriptObject| a == b)
+        .register_fn("!=", |a: ScriptObject, b: ScriptObject| a != b)
+        .register_fn("to_string", |o: &mut ScriptObject| {
+            with(o, SceneObject::name)
+        })
+        .register_fn("to_debug", |o: &mut ScriptObject| {
+            with(o, SceneObject::path)
+        });
+
+    // Transform, in parent space unless the name says otherwise
+    engine
+        .register_get_set(
+            "position",
+            |o: &mut ScriptObject| with(o, |o| o.get_transform().position()),
+            |o: &mut ScriptObject, position: Vec3| {
+                with(o, |o| modify(o, |t| t.set_position(position)))
+            },
+        )
+        .register_get_set(
+            "world_position",
+            |o: &mut ScriptObject| with(o, SceneObject::world_position),
+            |o: &mut ScriptObject, position: Vec3| with(o, |o| o.set_world_position(position)),
+        )
+        .register_get_set(
+            "rotation",
+            |o: &mut ScriptObject| with(o, |o| o.get_transform().euler_angles()),
+            |o: &mut ScriptObject, degrees: Vec3| {
+                with(o, |o| {
+                    modify(o, |t| t.set_rotation(Transform::euler_rotation(degrees)))
+                })
+            },
+        )
+        .register_get_set(
+            "scale",
+            |o: &mut ScriptObject| with(o, |o| o.get_transform().scale()),
+            |o: &mut ScriptObject, scale: Vec3| with(o, |o| modify(o, |t| t.set_scale(scale))),
+        )
+        .register_get("forward", |o: &mut ScriptObject| {
+            with(o, |o| o.get_transform().forward())
+        })
+        .register_get("right", |o: &mut ScriptObject| {
+            with(o, |o| o.get_transform().right())
+        })
+        .register_get("up", |o: &mut ScriptObject| {
+            with(o, |o| o.get_transform().up())
+        })
+        .register_fn("translate", |o: &mut ScriptObject, offset: Vec3| {
+            with(o, |o| modify(o, |t| t.set_position(t.position() + offset)))
+        })
+        .register_fn(
+            "rotate",
+            |o: &mut ScriptObject, axis: Vec3, degrees: FLOAT| {
+                with(o, |o| {
+                    if axis.magnitude2() > f32::EPSILON {
+                        modify(o, |t| t.rotate(axis, Deg(degrees as f32)))
+                    }
+                })
+            },
+        )
+        .register_fn("look_at", |o: &mut ScriptObject, target: Vec3| {
+            with(o, |o| {
+                modify(o, |t| {
+                    t.look_at(Point3::from_vec(target), Vector3::unit_y())
+                })
+            })
+        });
+
+    // A name or a path from the root like "Root/SceneObject 4/Child 2"
+    engine.register_fn("find_object", |name: &str| {
+        let found = with_context(|context| {
+            if name.contains('/') {
+                context.scene.find_path(name)
+            } else {
+                context.scene.find_by_name(name)
+            }
+        });
+        to_dynamic(found.flatten())
+    });
+}
+
+fn modify(object: &SceneObject, f: impl FnOnce(&mut Transform)) {
+    let mut transform = object.get_transform();
+    f(&mut transform);
+    object.set_transform(transform);
+}
+
+fn register_input(engine: &mut Engine) {
+    engine
+        .register_fn("key_down", |name: &str| {
+            with_context(|context| context.input.keys_down().any(|key| is_key(key, name)))
+                .unwrap_or(false)
+        })
+        .register_fn("key_pressed", |name: &str| {
+            with_context(|context| context.input.keys_pressed().any(|key| is_key(key, name)))
+                .unwrap_or(false)
+        })
+        .register_fn("mouse_down", |name: &str| {
+            with_context(|context| {
+                mouse_button(name).is_some_and(|button| context.input.mouse_down(button))
+            })
+            .unwrap_or(false)
+        })
+        .register_fn("mouse_position", || -> Array {
+            let [x, y] = with_context(|context| context.input.mouse_position()).unwrap_or_default();
+            vec![
+                Dynamic::from_float(x as FLOAT),
+                Dynamic::from_float(y as FLOAT),
+            ]
+        });
+}
+
+/// Whether `key` is called `name`, spelled like the `VirtualKeyCode` variants: "W", "Space",
+/// "Key1" or "Left", in any case.
+fn is_key(key: VirtualKeyCode, name: &str) -> bool {
+    format!("{key:?}").eq_ignore_ascii_case(name)
+}
+
+fn mouse_button(name: &str) -> Option<MouseButton> {
+    match name.to_lowercase().as_str() {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
@@ -1,15 +1,27 @@
 struct CameraUniform {
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
 }
 
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Locations have to stay below 16, the default vertex attribute limit
 struct InstanceInput{
-    @location(10) model_matrix_0: vec4<f32>,
-    @location(11) model_matrix_1: vec4<f32>,
-    @location(12) model_matrix_2: vec4<f32>,
-    @location(13) model_matrix_3: vec4<f32>,
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // Inverse transpose of the model matrix
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) object_id: u32,
 }
 
 struct VertexInput {
@@ -21,6 +33,10 @@ struct VertexInput {
 struct VertexOutput{
     @builtin(position) clip_position: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_normal: vec3<f32>,
+    // 0 is left for the background
+    @location(4) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -34,22 +50,286 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    out.object_id = instance.object_id;
     return out;
 }
 
 //Fragment
 
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var<uniform> material: MaterialUniform;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(2)
+var s_base_color: sampler;
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var s_normal: sampler;
+// g: roughness, b: metallic
+@group(0) @binding(5)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(6)
+var s_metallic_roughness: sampler;
+// r: occlusion
+@group(0) @binding(7)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(8)
+var s_occlusion: sampler;
+@group(0) @binding(9)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(10)
+var s_emissive: sampler;
+
+// Has to match MAX_LIGHTS in light.rs
+const MAX_LIGHTS: u32 = 16u;
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+const PI: f32 = 3.14159265359;
+// Reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: vec3<f32> = vec3<f32>(0.04, 0.04, 0.04);
+const MIN_ROUGHNESS: f32 = 0.045;
+
+struct Light {
+    // w: kind
+    position: vec4<f32>,
+    // w: range
+    direction: vec4<f32>,
+    // w: intensity
+    color: vec4<f32>,
+    // x: cos inner angle, y: cos outer angle
+    cone: vec4<f32>,
+    // x: first shadow layer or -1, y: layer count, z: bias, w: normal bias
+    shadow: vec4<f32>,
+}
+
+struct Lights {
+    lights: array<Light, MAX_LIGHTS>,
+    ambient: vec4<f32>,
+    count: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// Has to match MAX_SHADOW_LAYERS in shadow.rs
+const MAX_SHADOW_LAYERS: u32 = 8u;
+
+struct ShadowLayer {
+    view_proj: mat4x4<f32>,
+    // x: far split of a cascade, y: uv extent of the rendered region, z: size of a texel in uv
+    params: vec4<f32>,
+}
+
+@group(2) @binding(1)
+var<uniform> shadow_layers: array<ShadowLayer, MAX_SHADOW_LAYERS>;
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+
+// 3x3 percentage closer filtering, 1 is fully lit
+fn sample_shadow(layer: u32, world_position: vec3<f32>, bias: f32) -> f32 {
+    let shadow_layer = shadow_layers[layer];
+    let clip = shadow_layer.view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let region_uv = uv * shadow_layer.params.y;
+    let texel = shadow_layer.params.z;
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, region_uv + offset, i32(layer), ndc.z - bias);
+        }
+    }
+    return lit / 9.0;
+}
+
+fn shadow_factor(light: Light, world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow.x < 0.0 {
+        return 1.0;
+    }
+    let first = u32(light.shadow.x);
+    let count = u32(light.shadow.y);
+    let position = world_position + normal * light.shadow.w;
+
+    if count == 1u {
+        return sample_shadow(first, position, light.shadow.z);
+    }
+    // Cascades are picked by distance to the camera, past the last one there is no shadow
+    let distance = length(world_position - camera.view_position.xyz);
+    for (var i = 0u; i < count; i += 1u) {
+        if distance < shadow_layers[first + i].params.x {
+            return sample_shadow(first + i, position, light.shadow.z);
+        }
+    }
+    return 1.0;
+}
+
+// Screen space derivatives of the surface. Only the fragment entry points take them, the GL
+// backend also emits the helper functions into the vertex shader where dpdx does not exist.
+struct Derivatives {
+    position_dx: vec3<f32>,
+    position_dy: vec3<f32>,
+    uv_dx: vec2<f32>,
+    uv_dy: vec2<f32>,
+}
+
+// Tangent frame from screen space derivatives, so meshes need no tangents
+fn perturb_normal(normal: vec3<f32>, d: Derivatives, map: vec3<f32>) -> vec3<f32> {
+    let dp1 = d.position_dx;
+    let dp2 = d.position_dy;
+    let duv1 = d.uv_dx;
+    let duv2 = d.uv_dy;
 
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+
+    let scale = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if scale <= 0.0 {
+        // No usable uvs
+        return normal;
+    }
+    let inv_scale = inverseSqrt(scale);
+    let tbn = mat3x3<f32>(tangent * inv_scale, bitangent * inv_scale, normal);
+    return normalize(tbn * map);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, k: f32) -> f32 {
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn shade(in: VertexOutput, d: Derivatives) -> vec4<f32> {
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords) * material.base_color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+    let roughness = clamp(metallic_roughness.g * material.roughness, MIN_ROUGHNESS, 1.0);
+    let occlusion = mix(1.0, textureSample(t_occlusion, s_occlusion, in.tex_coords).r, material.occlusion_strength);
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive.rgb;
+
+    let normal_map = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    let normal = perturb_normal(
+        normalize(in.world_normal),
+        d,
+        normal_map * vec3<f32>(material.normal_scale, material.normal_scale, 1.0)
+    );
+    let view_dir = normalize(camera.view_position.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+
+    let albedo = base_color.rgb;
+    let f0 = mix(DIELECTRIC_F0, albedo, metallic);
+
+    var color = lights.ambient.rgb * albedo * occlusion;
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i += 1u) {
+        let light = lights.lights[i];
+        let kind = u32(light.position.w);
+
+        var light_dir: vec3<f32>;
+        var attenuation = 1.0;
+        if kind == LIGHT_DIRECTIONAL {
+            light_dir = -light.direction.xyz;
+        } else {
+            let to_light = light.position.xyz - in.world_position;
+            let distance = length(to_light);
+            light_dir = to_light / distance;
+
+            // Inverse square falloff, smoothly windowed to reach zero at the range
+            let window = clamp(1.0 - pow(distance / light.direction.w, 4.0), 0.0, 1.0);
+            attenuation = window * window / (distance * distance + 1.0);
+
+            if kind == LIGHT_SPOT {
+                let cos_angle = dot(-light_dir, light.direction.xyz);
+                attenuation *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+            }
+        }
+
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let half_dir = normalize(light_dir + view_dir);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+
+        // Cook-Torrance specular with a lambert diffuse for the non metallic part
+        let fresnel = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+        let specular = distribution_ggx(n_dot_h, roughness)
+            * geometry_smith(n_dot_v, n_dot_l, roughness)
+            * fresnel / (4.0 * n_dot_v * n_dot_l + 0.0001);
+        let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+
+        let shadow = shadow_factor(light, in.world_position, normalize(in.world_normal));
+        let radiance = light.color.rgb * light.color.w * attenuation * shadow;
+        color += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    return vec4<f32>(color + emissive, base_color.a);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let d = Derivatives(dpdx(in.world_position), dpdy(in.world_position), dpdx(in.tex_coords), dpdy(in.tex_coords));
+    return shade(in, d);
+}
+
+struct PickingOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) object_id: u32,
+}
+
+// Also writes the id of the drawn instance for picking
+@fragment
+fn fs_picking(in: VertexOutput) -> PickingOutput {
+    let d = Derivatives(dpdx(in.world_position), dpdy(in.world_position), dpdx(in.tex_coords), dpdy(in.tex_coords));
+    var out: PickingOutput;
+    out.color = shade(in, d);
+    out.object_id = in.object_id;
+    return out;
+}
@@ -258,7 +258,7 @@ pub mod ui {
 
     pub fn input_text(ui: &Ui, label: &str, text: &mut String, hint: Option<&str>) -> bool {
         text_label(ui, label);
-        let id = "##".to_string() + &label.to_string();
+        let id = "##".to_string() + label;
         let mut t = ui.input_text(id, text);
         if let Some(hint) = hint {
             t = t.hint(hint);
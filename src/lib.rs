@@ -0,0 +1,15 @@
+//! The engine behind the editor. Crates using it can add their own component types with
+//! `register_component` before loading scenes containing them.
+
+mod app;
+mod gui;
+
+pub use app::{
+    assets::AssetDatabase,
+    golden, render_headless,
+    scene::{
+        component::{register_component, Component, ComponentIdentifier},
+        BehaviourContext, Scene, SceneObject,
+    },
+    ApplicationState,
+};
@@ -1,26 +1,67 @@
-mod app;
-mod gui;
 mod tree;
 
-use std::time::Instant;
+use std::{path::Path, time::Instant};
 
-use app::ApplicationState;
+use engine_wgpu::ApplicationState;
+#[cfg(target_os = "windows")]
+use winit::platform::windows::WindowBuilderExtWindows;
 use winit::{
     dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::EventLoop,
-    platform::windows::WindowBuilderExtWindows,
     window::{Icon, WindowBuilder},
 };
 
 const ICON_DATA: &'static [u8] = include_bytes!("engine_icon.png");
+#[cfg(target_os = "windows")]
 const ICON_DATA_BIG: &'static [u8] = include_bytes!("engine_icon.png");
 
 fn main() {
     env_logger::init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        Some("--headless") => {
+            if let Err(e) = pollster::block_on(headless(&args[1..])) {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("--golden") => {
+            let dir = args[1..]
+                .iter()
+                .find(|a| !a.starts_with("--"))
+                .map_or("golden", String::as_str);
+            let bless = args.iter().any(|a| a == "--bless");
+            match pollster::block_on(engine_wgpu::golden::run(Path::new(dir), bless)) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => {}
+    }
+
     pollster::block_on(run());
 }
 
+async fn headless(args: &[String]) -> anyhow::Result<()> {
+    const USAGE: &str = "Usage: engine_wgpu --headless <scene.yaml> <output.png> [width height]";
+    let [scene, output, size @ ..] = args else {
+        anyhow::bail!(USAGE);
+    };
+    let (width, height) = match size {
+        [] => (800, 600),
+        [width, height] => (width.parse()?, height.parse()?),
+        _ => anyhow::bail!(USAGE),
+    };
+    engine_wgpu::render_headless(Path::new(scene), Path::new(output), width, height).await
+}
+
 async fn run() {
     #[cfg(feature = "render-doc")]
     let mut rd: renderdoc::RenderDoc<renderdoc::V141> =
@@ -30,15 +71,17 @@ async fn run() {
     rd.start_frame_capture(std::ptr::null(), std::ptr::null());
 
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
+    let builder = WindowBuilder::new()
         .with_inner_size(PhysicalSize::new(800, 600))
         .with_resizable(true)
-        .with_theme(Some(winit::window::Theme::Dark))
         .with_title("Engine WGPU")
-        .with_window_icon(Some(make_icon(ICON_DATA)))
-        .with_taskbar_icon(Some(make_icon(ICON_DATA_BIG)))
-        .build(&event_loop)
-        .unwrap();
+        .with_window_icon(Some(make_icon(ICON_DATA)));
+    // The theme and taskbar icon here come from WindowBuilderExtWindows, only built on Windows
+    #[cfg(target_os = "windows")]
+    let builder = builder
+        .with_theme(Some(winit::window::Theme::Dark))
+        .with_taskbar_icon(Some(make_icon(ICON_DATA_BIG)));
+    let window = builder.build(&event_loop).unwrap();
 
     let mut state = ApplicationState::new(window).await;
 
@@ -67,12 +67,13 @@ where
     fn print(&self) -> String {
         format!("{:?}", &self.object)
             + "["
-            + &self
+            + self
                 .childs
                 .iter()
                 .map(|c| c.print())
                 .collect::<Vec<String>>()
                 .join(",")
+                .as_str()
             + "]"
     }
 